@@ -4,10 +4,30 @@ use std::{env, path::PathBuf};
 fn main() -> Result<()> {
     // prost_build::compile_protos(&["proto/napoli.proto"], &["proto/"])?;
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let descriptor_path = out_dir.join("napoli_descriptor.bin");
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
-        .file_descriptor_set_path(out_dir.join("napoli_descriptor.bin"))
-        .compile(&["proto/models.proto", "proto/comms.proto"], &["proto/"])?;
+        .file_descriptor_set_path(&descriptor_path)
+        .compile(
+            &[
+                "proto/models.proto",
+                "proto/comms.proto",
+                "proto/napoli/v2/napoli.proto",
+                "proto/google/rpc/status.proto",
+                "proto/google/rpc/error_details.proto",
+            ],
+            &["proto/"],
+        )?;
+
+    #[cfg(feature = "serde")]
+    {
+        let descriptor_set = std::fs::read(&descriptor_path)?;
+        pbjson_build::Builder::new()
+            .register_descriptors(&descriptor_set)?
+            .exclude([".napoli.v2"])
+            .build(&[".napoli"])?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,217 @@
+//! Machine-readable details of failed calls.
+//!
+//! Besides its code and message, every status the server returns carries a `google.rpc.ErrorInfo`
+//! with a [`Reason`] and, if the request was invalid, a `google.rpc.BadRequest` naming the
+//! offending fields. Both travel in the `grpc-status-details-bin` trailer.
+
+use bytes::Bytes;
+use prost::Message;
+
+/// The `google.rpc` messages
+pub mod pb {
+    tonic::include_proto!("google.rpc");
+}
+
+/// `domain` of all `ErrorInfo`s of napoli
+pub const DOMAIN: &str = "napoli";
+
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    FieldEmpty,
+    FieldTooLong,
+    InvalidPrice,
+    PriceTooHigh,
+    UnknownCurrency,
+    CurrencyMismatch,
+    /// A field has a value the call doesn't support, e.g. an unknown path in a field mask
+    InvalidField,
+    OrderNotFound,
+    OrderEntryNotFound,
+    OrderNotOpen,
+    VersionMismatch,
+    TooManyStreams,
+    StorageError,
+}
+
+const REASONS: [Reason; 13] = [
+    Reason::FieldEmpty,
+    Reason::FieldTooLong,
+    Reason::InvalidPrice,
+    Reason::PriceTooHigh,
+    Reason::UnknownCurrency,
+    Reason::CurrencyMismatch,
+    Reason::InvalidField,
+    Reason::OrderNotFound,
+    Reason::OrderEntryNotFound,
+    Reason::OrderNotOpen,
+    Reason::VersionMismatch,
+    Reason::TooManyStreams,
+    Reason::StorageError,
+];
+
+impl Reason {
+    /// The `reason` of the `ErrorInfo`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::FieldEmpty => "FIELD_EMPTY",
+            Reason::FieldTooLong => "FIELD_TOO_LONG",
+            Reason::InvalidPrice => "INVALID_PRICE",
+            Reason::PriceTooHigh => "PRICE_TOO_HIGH",
+            Reason::UnknownCurrency => "UNKNOWN_CURRENCY",
+            Reason::CurrencyMismatch => "CURRENCY_MISMATCH",
+            Reason::InvalidField => "INVALID_FIELD",
+            Reason::OrderNotFound => "ORDER_NOT_FOUND",
+            Reason::OrderEntryNotFound => "ORDER_ENTRY_NOT_FOUND",
+            Reason::OrderNotOpen => "ORDER_NOT_OPEN",
+            Reason::VersionMismatch => "VERSION_MISMATCH",
+            Reason::TooManyStreams => "TOO_MANY_STREAMS",
+            Reason::StorageError => "STORAGE_ERROR",
+        }
+    }
+
+    pub fn parse(reason: &str) -> Option<Reason> {
+        REASONS.into_iter().find(|known| known.as_str() == reason)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldViolation {
+    /// Name of the field in the request message, e.g. `food`
+    pub field: String,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorDetails {
+    /// `None` for statuses without details and for reasons this version doesn't know
+    pub reason: Option<Reason>,
+    pub field_violations: Vec<FieldViolation>,
+}
+
+impl ErrorDetails {
+    pub fn new(reason: Reason) -> Self {
+        ErrorDetails {
+            reason: Some(reason),
+            field_violations: vec![],
+        }
+    }
+
+    pub fn with_violation(mut self, field: &str, description: impl Into<String>) -> Self {
+        self.field_violations.push(FieldViolation {
+            field: field.to_owned(),
+            description: description.into(),
+        });
+        self
+    }
+
+    /// The description of what is wrong with `field`, if anything
+    pub fn violation(&self, field: &str) -> Option<&str> {
+        self.field_violations
+            .iter()
+            .find(|violation| violation.field == field)
+            .map(|violation| violation.description.as_str())
+    }
+
+    pub fn into_status(self, code: tonic::Code, message: impl Into<String>) -> tonic::Status {
+        let message = message.into();
+
+        let mut details = vec![];
+        if let Some(reason) = self.reason {
+            let error_info = pb::ErrorInfo {
+                reason: reason.as_str().to_owned(),
+                domain: DOMAIN.to_owned(),
+                metadata: Default::default(),
+            };
+            details.push(prost_types::Any {
+                type_url: ERROR_INFO_TYPE_URL.to_owned(),
+                value: error_info.encode_to_vec(),
+            });
+        }
+        if !self.field_violations.is_empty() {
+            let bad_request = pb::BadRequest {
+                field_violations: self
+                    .field_violations
+                    .into_iter()
+                    .map(|violation| pb::bad_request::FieldViolation {
+                        field: violation.field,
+                        description: violation.description,
+                    })
+                    .collect(),
+            };
+            details.push(prost_types::Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_owned(),
+                value: bad_request.encode_to_vec(),
+            });
+        }
+
+        let status = pb::Status {
+            code: code as i32,
+            message: message.clone(),
+            details,
+        };
+        tonic::Status::with_details(code, message, Bytes::from(status.encode_to_vec()))
+    }
+
+    /// Details that can't be decoded are skipped
+    pub fn from_status(status: &tonic::Status) -> Self {
+        let mut error_details = ErrorDetails::default();
+        let details = match pb::Status::decode(status.details()) {
+            Ok(status) => status.details,
+            Err(_) => return error_details,
+        };
+
+        for detail in details {
+            match detail.type_url.as_str() {
+                ERROR_INFO_TYPE_URL => {
+                    if let Ok(error_info) = pb::ErrorInfo::decode(detail.value.as_slice()) {
+                        if error_info.domain == DOMAIN {
+                            error_details.reason = Reason::parse(&error_info.reason);
+                        }
+                    }
+                }
+                BAD_REQUEST_TYPE_URL => {
+                    if let Ok(bad_request) = pb::BadRequest::decode(detail.value.as_slice()) {
+                        error_details.field_violations.extend(
+                            bad_request.field_violations.into_iter().map(|violation| {
+                                FieldViolation {
+                                    field: violation.field,
+                                    description: violation.description,
+                                }
+                            }),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        error_details
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn details_survive_the_status() {
+        let details = ErrorDetails::new(Reason::FieldTooLong)
+            .with_violation("food", "must not be longer than 210 characters");
+        let status = details
+            .clone()
+            .into_status(tonic::Code::InvalidArgument, "food is too long");
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(ErrorDetails::from_status(&status), details);
+        assert_eq!(
+            details.violation("food"),
+            Some("must not be longer than 210 characters")
+        );
+        assert_eq!(details.violation("buyer"), None);
+
+        let plain = tonic::Status::internal("oops");
+        assert_eq!(ErrorDetails::from_status(&plain), ErrorDetails::default());
+    }
+}
@@ -1,16 +1,49 @@
+pub mod error_details;
 pub mod limits;
 pub mod millicents;
+pub mod money;
+pub mod validate;
 pub use millicents::Millicents;
+pub use money::{Currency, Money};
 
 pub mod napoli {
+    // The generated tonic code predates some newer clippy lints
+    #![allow(clippy::result_large_err, clippy::double_must_use)]
+
     tonic::include_proto!("napoli");
 
+    #[cfg(feature = "serde")]
+    #[allow(clippy::all)]
+    mod serde_impls {
+        use super::*;
+        include!(concat!(env!("OUT_DIR"), "/napoli.serde.rs"));
+    }
+
+    /// Version 2 of the protocol, see `proto/napoli/v2/napoli.proto`. It has no JSON mapping
+    pub mod v2 {
+        tonic::include_proto!("napoli.v2");
+    }
+
     pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("napoli_descriptor");
 
     pub type ObjectId = i32;
 
     impl Eq for Order {}
     impl Eq for OrderEntry {}
+
+    impl Order {
+        /// Whether the server found values in the order or its entries that it would never
+        /// have accepted, see `integrity_warnings`
+        pub fn is_tainted(&self) -> bool {
+            !self.integrity_warnings.is_empty()
+        }
+    }
+
+    impl OrderEntry {
+        pub fn is_tainted(&self) -> bool {
+            !self.integrity_warnings.is_empty()
+        }
+    }
 }
 
 pub fn create_example_order() -> napoli::Order {
@@ -28,6 +61,7 @@ pub fn create_example_order() -> napoli::Order {
         price_deprecated: 0.0,
         price_in_millicents: 10000,
         paid: false,
+        ..Default::default()
     };
     order.entries.push(entry);
 
@@ -38,6 +72,7 @@ pub fn create_example_order() -> napoli::Order {
         price_deprecated: 0.0,
         price_in_millicents: 10000,
         paid: false,
+        ..Default::default()
     };
 
     order.entries.push(entry);
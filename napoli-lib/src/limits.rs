@@ -1 +1,2 @@
+/// Maximum number of characters, not bytes, of food, buyer and menu URL
 pub const MAX_STR_LEN: usize = 210;
@@ -1,23 +1,148 @@
+//! Exact amounts of money. A millicent is a thousandth of a cent, so 100000 millicents make a euro
+//! or a franc. Amounts don't know their currency, [`crate::Money`] adds it.
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, Mul};
+use std::str::FromStr;
+
+const MILLICENTS_PER_EURO: i64 = 100_000;
+const MILLICENTS_PER_CENT: i64 = 1_000;
+/// Digits after the decimal separator that still fit into millicents
+const MAX_DECIMALS: usize = 5;
+
+/// Never negative. With the `serde` feature it (de)serializes as the raw number of millicents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "i64", into = "i64")
+)]
 pub struct Millicents(i64);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MillicentsError {
     Negative,
     ParseError,
+    /// More than five digits after the decimal separator
+    TooPrecise,
+    Overflow,
+}
+
+impl fmt::Display for MillicentsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MillicentsError::Negative => write!(f, "amount is negative"),
+            MillicentsError::ParseError => write!(f, "not an amount of money"),
+            MillicentsError::TooPrecise => write!(f, "more precise than a thousandth of a cent"),
+            MillicentsError::Overflow => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl std::error::Error for MillicentsError {}
+
+/// How [`Millicents::format`] writes amounts. Amounts are rounded up to whole cents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MillicentsFormat {
+    pub decimal_separator: char,
+    /// `None` leaves out the currency
+    pub currency_symbol: Option<&'static str>,
+    pub symbol_before_amount: bool,
+    /// Between amount and currency symbol
+    pub symbol_separator: &'static str,
+}
+
+impl MillicentsFormat {
+    /// `10.50 €`, with a non-breaking space, which is also what `Display` writes
+    pub const DOT: MillicentsFormat = MillicentsFormat {
+        decimal_separator: '.',
+        currency_symbol: Some("€"),
+        symbol_before_amount: false,
+        symbol_separator: "\u{00a0}",
+    };
+    /// `10,50 €`
+    pub const GERMAN: MillicentsFormat = MillicentsFormat {
+        decimal_separator: ',',
+        ..MillicentsFormat::DOT
+    };
+    /// `10.50`
+    pub const PLAIN: MillicentsFormat = MillicentsFormat {
+        currency_symbol: None,
+        ..MillicentsFormat::DOT
+    };
+}
+
+impl Default for MillicentsFormat {
+    fn default() -> Self {
+        MillicentsFormat::DOT
+    }
 }
 
 impl Millicents {
+    /// Rounds to whole millicents. Only for the deprecated float prices of the protocol
     pub fn from_euro_float(euros: f64) -> Result<Self, MillicentsError> {
+        if euros.is_nan() {
+            return Err(MillicentsError::ParseError);
+        }
         if euros < 0.0 {
             return Err(MillicentsError::Negative);
         }
 
-        Ok(Millicents((euros * 100000.0) as i64))
+        let millicents = (euros * MILLICENTS_PER_EURO as f64).round();
+        if millicents >= i64::MAX as f64 {
+            return Err(MillicentsError::Overflow);
+        }
+        Ok(Millicents(millicents as i64))
     }
 
+    /// Parses amounts like `10.50`, `10,5`, `10,50 €` or `€ 3` exactly. See
+    /// [`crate::Money::parse`] for other currencies
     pub fn from_euro_human(s: &str) -> Result<Self, MillicentsError> {
-        let f: f64 = s.parse::<f64>().map_err(|_| MillicentsError::ParseError)?;
-        Self::from_euro_float(f)
+        let s = s.trim();
+        let s = s
+            .strip_suffix('€')
+            .or_else(|| s.strip_prefix('€'))
+            .unwrap_or(s);
+        Millicents::from_decimal(s)
+    }
+
+    /// Parses amounts without a currency like `10.50` or `10,5` exactly
+    pub fn from_decimal(s: &str) -> Result<Self, MillicentsError> {
+        let s = s.trim();
+        if s.starts_with('-') {
+            return Err(MillicentsError::Negative);
+        }
+
+        let (euros, decimals) = match s.find(['.', ',']) {
+            Some(separator) => (&s[..separator], &s[separator + 1..]),
+            None => (s, ""),
+        };
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if euros.is_empty() && decimals.is_empty() || !is_digits(euros) || !is_digits(decimals) {
+            return Err(MillicentsError::ParseError);
+        }
+        if decimals.len() > MAX_DECIMALS {
+            return Err(MillicentsError::TooPrecise);
+        }
+
+        // Only digits are left, so parsing can only fail by overflowing
+        let euros: i64 = match euros {
+            "" => 0,
+            euros => euros.parse().map_err(|_| MillicentsError::Overflow)?,
+        };
+        let decimals: i64 = match decimals {
+            "" => 0,
+            decimals => {
+                decimals.parse::<i64>().unwrap()
+                    * 10_i64.pow((MAX_DECIMALS - decimals.len()) as u32)
+            }
+        };
+        euros
+            .checked_mul(MILLICENTS_PER_EURO)
+            .and_then(|millicents| millicents.checked_add(decimals))
+            .map(Millicents)
+            .ok_or(MillicentsError::Overflow)
     }
 
     pub fn from_raw(i: i64) -> Result<Self, MillicentsError> {
@@ -48,10 +173,124 @@ impl Millicents {
         self.0 == 0
     }
 
+    pub fn checked_add(self, other: Millicents) -> Option<Millicents> {
+        self.0.checked_add(other.0).map(Millicents)
+    }
+
+    /// The price of `quantity` items of this price
+    pub fn checked_mul(self, quantity: u32) -> Option<Millicents> {
+        self.0.checked_mul(quantity.into()).map(Millicents)
+    }
+
+    /// Splits the amount into one part per ratio, proportional to the ratios, so that the parts
+    /// sum up to exactly the amount. Each part is its exact share rounded down, and the
+    /// millicents left over go to the parts with the largest remainders, to earlier parts on
+    /// ties. `None` if there are no ratios or all of them are zero
+    pub fn allocate(self, ratios: &[u32]) -> Option<Vec<Millicents>> {
+        let total_ratio: i128 = ratios.iter().map(|&ratio| i128::from(ratio)).sum();
+        if total_ratio == 0 {
+            return None;
+        }
+
+        // i64 amounts times u32 ratios always fit into i128
+        let amount = i128::from(self.0);
+        let mut parts = Vec::with_capacity(ratios.len());
+        let mut remainders = Vec::with_capacity(ratios.len());
+        for (index, &ratio) in ratios.iter().enumerate() {
+            let share = amount * i128::from(ratio);
+            parts.push(share / total_ratio);
+            remainders.push((share % total_ratio, index));
+        }
+
+        // Less than one millicent per part is left over
+        let left_over = amount - parts.iter().sum::<i128>();
+        remainders.sort_by(|(a, a_index), (b, b_index)| b.cmp(a).then(a_index.cmp(b_index)));
+        for &(_, index) in remainders.iter().take(left_over as usize) {
+            parts[index] += 1;
+        }
+
+        Some(
+            parts
+                .into_iter()
+                .map(|part| Millicents(part as i64))
+                .collect(),
+        )
+    }
+
+    /// Splits the amount into `parts` parts that differ by at most a millicent, the larger ones
+    /// first. `None` for zero parts
+    pub fn allocate_evenly(self, parts: usize) -> Option<Vec<Millicents>> {
+        self.allocate(&vec![1; parts])
+    }
+
+    pub fn format(&self, format: &MillicentsFormat) -> String {
+        let (euros, cents) = self.to_euro_tuple();
+        let amount = format!("{}{}{:02}", euros, format.decimal_separator, cents);
+        match format.currency_symbol {
+            Some(symbol) if format.symbol_before_amount => {
+                format!("{}{}{}", symbol, format.symbol_separator, amount)
+            }
+            Some(symbol) => format!("{}{}{}", amount, format.symbol_separator, symbol),
+            None => amount,
+        }
+    }
+
     fn millicents_to_euro(mc: i64) -> (i64, i64) {
-        let euros = mc / 100000;
-        let cents = divide_and_round(mc - euros * 100000, 1000);
-        (euros, cents)
+        let cents = divide_and_round(mc, MILLICENTS_PER_CENT);
+        (cents / 100, cents % 100)
+    }
+}
+
+impl fmt::Display for Millicents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(&MillicentsFormat::default()))
+    }
+}
+
+impl FromStr for Millicents {
+    type Err = MillicentsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Millicents::from_euro_human(s)
+    }
+}
+
+impl TryFrom<i64> for Millicents {
+    type Error = MillicentsError;
+
+    fn try_from(millicents: i64) -> Result<Self, Self::Error> {
+        Millicents::from_raw(millicents)
+    }
+}
+
+impl From<Millicents> for i64 {
+    fn from(millicents: Millicents) -> Self {
+        millicents.0
+    }
+}
+
+/// `None` on overflow
+impl Add for Millicents {
+    type Output = Option<Millicents>;
+
+    fn add(self, other: Millicents) -> Option<Millicents> {
+        self.checked_add(other)
+    }
+}
+
+/// Multiplies by a quantity, `None` on overflow
+impl Mul<u32> for Millicents {
+    type Output = Option<Millicents>;
+
+    fn mul(self, quantity: u32) -> Option<Millicents> {
+        self.checked_mul(quantity)
+    }
+}
+
+/// `None` on overflow
+impl Sum<Millicents> for Option<Millicents> {
+    fn sum<I: Iterator<Item = Millicents>>(mut iter: I) -> Self {
+        iter.try_fold(Millicents::zero(), Millicents::checked_add)
     }
 }
 
@@ -66,7 +305,8 @@ pub fn divide_and_round(dividend: i64, divisor: i64) -> i64 {
 
 #[cfg(test)]
 mod tests {
-    use crate::millicents::Millicents;
+    use crate::millicents::{Millicents, MillicentsError, MillicentsFormat};
+    use proptest::prelude::*;
 
     #[test]
     fn test_millicent_to_euro() {
@@ -74,6 +314,7 @@ mod tests {
         assert_eq!(Millicents::millicents_to_euro(mc_price), (13, 37));
         assert_eq!(Millicents::millicents_to_euro(133736999), (1337, 37));
         assert_eq!(Millicents::millicents_to_euro(1337000), (13, 37));
+        assert_eq!(Millicents::millicents_to_euro(1399999), (14, 0));
     }
 
     #[test]
@@ -83,5 +324,144 @@ mod tests {
             Millicents::from_raw(1337000).unwrap().to_euro_float(),
             13.37
         );
+        assert_eq!(Millicents::from_euro_float(0.29).unwrap().raw(), 29000);
+    }
+
+    #[test]
+    fn parses_human_amounts_exactly() {
+        let parse = |s: &str| s.parse::<Millicents>().map(|price| price.raw());
+        assert_eq!(parse("10.50"), Ok(1_050_000));
+        assert_eq!(parse("10,5"), Ok(1_050_000));
+        assert_eq!(parse(" 10,50\u{00a0}€ "), Ok(1_050_000));
+        assert_eq!(parse("€3"), Ok(300_000));
+        assert_eq!(parse(",99"), Ok(99_000));
+        assert_eq!(parse("0.29"), Ok(29_000));
+        assert_eq!(parse("0.00001"), Ok(1));
+
+        assert_eq!(parse("0.000001"), Err(MillicentsError::TooPrecise));
+        assert_eq!(parse("-1"), Err(MillicentsError::Negative));
+        assert_eq!(parse("1.000,50"), Err(MillicentsError::ParseError));
+        assert_eq!(parse("€"), Err(MillicentsError::ParseError));
+        assert_eq!(parse("1e3"), Err(MillicentsError::ParseError));
+        assert_eq!(
+            parse("99999999999999999999"),
+            Err(MillicentsError::Overflow)
+        );
+    }
+
+    #[test]
+    fn formats_amounts() {
+        let price = Millicents::from_raw(1_050_000).unwrap();
+        assert_eq!(price.to_string(), "10.50\u{00a0}€");
+        assert_eq!(price.format(&MillicentsFormat::GERMAN), "10,50\u{00a0}€");
+        assert_eq!(price.format(&MillicentsFormat::PLAIN), "10.50");
+        let before = MillicentsFormat {
+            symbol_before_amount: true,
+            symbol_separator: "",
+            ..MillicentsFormat::DOT
+        };
+        assert_eq!(price.format(&before), "€10.50");
+        assert_eq!(price.to_string().parse(), Ok(price));
+    }
+
+    #[test]
+    fn arithmetic_is_checked() {
+        let price = Millicents::from_raw(1_050_000).unwrap();
+        assert_eq!(
+            price + price,
+            Some(Millicents::from_raw(2_100_000).unwrap())
+        );
+        assert_eq!(price * 3, Some(Millicents::from_raw(3_150_000).unwrap()));
+        assert_eq!(
+            [price, price, price]
+                .into_iter()
+                .sum::<Option<Millicents>>(),
+            price * 3
+        );
+
+        let max = Millicents::from_raw(i64::MAX).unwrap();
+        assert_eq!(max + price, None);
+        assert_eq!(max * 2, None);
+        assert_eq!([max, price].into_iter().sum::<Option<Millicents>>(), None);
+    }
+
+    #[test]
+    fn allocates_by_largest_remainder() {
+        let millicents = |raw| Millicents::from_raw(raw).unwrap();
+        let raw = |parts: Vec<Millicents>| parts.iter().map(Millicents::raw).collect::<Vec<_>>();
+
+        assert_eq!(
+            raw(millicents(100).allocate_evenly(3).unwrap()),
+            [34, 33, 33]
+        );
+        assert_eq!(
+            raw(millicents(5).allocate(&[1, 1, 1, 1]).unwrap()),
+            [2, 1, 1, 1]
+        );
+        assert_eq!(raw(millicents(100).allocate(&[1, 2]).unwrap()), [33, 67]);
+        assert_eq!(
+            raw(millicents(10).allocate(&[0, 3, 0, 7]).unwrap()),
+            [0, 3, 0, 7]
+        );
+        assert_eq!(
+            raw(millicents(2).allocate_evenly(5).unwrap()),
+            [1, 1, 0, 0, 0]
+        );
+        assert_eq!(
+            raw(millicents(i64::MAX).allocate(&[u32::MAX, 1]).unwrap())
+                .into_iter()
+                .map(i128::from)
+                .sum::<i128>(),
+            i128::from(i64::MAX)
+        );
+
+        assert_eq!(millicents(100).allocate(&[]), None);
+        assert_eq!(millicents(100).allocate(&[0, 0]), None);
+        assert_eq!(millicents(100).allocate_evenly(0), None);
+    }
+
+    proptest! {
+        #[test]
+        fn allocated_parts_sum_up_to_the_amount(
+            amount in 0..=i64::MAX,
+            ratios in proptest::collection::vec(0..=u32::MAX, 1..20),
+        ) {
+            let total_ratio: i128 = ratios.iter().map(|&ratio| i128::from(ratio)).sum();
+            prop_assume!(total_ratio > 0);
+
+            let parts = Millicents::from_raw(amount).unwrap().allocate(&ratios).unwrap();
+            prop_assert_eq!(parts.len(), ratios.len());
+            let sum: i128 = parts.iter().map(|part| i128::from(part.raw())).sum();
+            prop_assert_eq!(sum, i128::from(amount));
+
+            for (part, &ratio) in parts.iter().zip(&ratios) {
+                // Within a millicent of the exact share
+                let share = i128::from(amount) * i128::from(ratio);
+                let part = i128::from(part.raw()) * total_ratio;
+                prop_assert!(part > share - total_ratio && part < share + total_ratio);
+            }
+        }
+
+        #[test]
+        fn even_parts_differ_by_at_most_a_millicent(amount in 0..=i64::MAX, parts in 1..100usize) {
+            let parts = Millicents::from_raw(amount).unwrap().allocate_evenly(parts).unwrap();
+            prop_assert_eq!(parts.iter().copied().sum::<Option<Millicents>>().unwrap().raw(), amount);
+            let largest = parts.iter().max().unwrap().raw();
+            let smallest = parts.iter().min().unwrap().raw();
+            prop_assert!(largest - smallest <= 1);
+            prop_assert!(parts.windows(2).all(|pair| pair[0] >= pair[1]));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_as_raw_millicents() {
+        let price = Millicents::from_raw(1_050_000).unwrap();
+        assert_eq!(serde_json::to_string(&price).unwrap(), "1050000");
+        assert_eq!(
+            serde_json::from_str::<Millicents>("1050000").unwrap(),
+            price
+        );
+        assert!(serde_json::from_str::<Millicents>("-1").is_err());
     }
 }
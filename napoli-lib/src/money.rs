@@ -0,0 +1,273 @@
+//! Amounts of money in a currency. Every order has a single currency, and only amounts of the
+//! same currency can be added up.
+
+use std::fmt;
+
+use crate::millicents::{Millicents, MillicentsError, MillicentsFormat};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Currency {
+    #[default]
+    Eur,
+    Chf,
+}
+
+const CURRENCIES: [Currency; 2] = [Currency::Eur, Currency::Chf];
+
+impl Currency {
+    /// ISO 4217 code, which is how the protocol and the database store currencies
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Eur => "EUR",
+            Currency::Chf => "CHF",
+        }
+    }
+
+    /// Case-insensitive. The empty code is euros, the currency of all orders from before orders
+    /// had one
+    pub fn from_code(code: &str) -> Option<Currency> {
+        let code = code.trim();
+        if code.is_empty() {
+            return Some(Currency::Eur);
+        }
+        CURRENCIES
+            .into_iter()
+            .find(|currency| currency.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn all() -> [Currency; 2] {
+        CURRENCIES
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Currency::Eur => "€",
+            Currency::Chf => "CHF",
+        }
+    }
+
+    /// How amounts are written where the currency is used: `10.50 €` and `CHF 10.50`
+    pub fn format(&self) -> MillicentsFormat {
+        match self {
+            Currency::Eur => MillicentsFormat::DOT,
+            Currency::Chf => MillicentsFormat {
+                currency_symbol: Some(self.symbol()),
+                symbol_before_amount: true,
+                ..MillicentsFormat::DOT
+            },
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    CurrencyMismatch {
+        expected: Currency,
+        actual: Currency,
+    },
+    Amount(MillicentsError),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch { expected, actual } => {
+                write!(f, "amount is in {}, not in {}", actual, expected)
+            }
+            MoneyError::Amount(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl From<MillicentsError> for MoneyError {
+    fn from(err: MillicentsError) -> Self {
+        MoneyError::Amount(err)
+    }
+}
+
+/// A [`Millicents`] amount of a [`Currency`], where a millicent is a thousandth of a hundredth of
+/// the currency's unit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Money {
+    amount: Millicents,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: Millicents, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(Millicents::zero(), currency)
+    }
+
+    pub fn from_raw(millicents: i64, currency: Currency) -> Result<Self, MoneyError> {
+        Ok(Money::new(Millicents::from_raw(millicents)?, currency))
+    }
+
+    /// Parses amounts like `10.50`, `10,5`, `CHF 3` or `10,50 €` exactly. Symbols and codes of
+    /// other currencies are rejected
+    pub fn parse(s: &str, currency: Currency) -> Result<Self, MoneyError> {
+        let s = s.trim();
+        let amount = [currency.symbol(), currency.code()]
+            .into_iter()
+            .find_map(|marker| s.strip_prefix(marker).or_else(|| s.strip_suffix(marker)))
+            .unwrap_or(s);
+        for other in Currency::all()
+            .into_iter()
+            .filter(|&other| other != currency)
+        {
+            if amount.contains(other.symbol()) || amount.contains(other.code()) {
+                return Err(MoneyError::CurrencyMismatch {
+                    expected: currency,
+                    actual: other,
+                });
+            }
+        }
+        Ok(Money::new(Millicents::from_decimal(amount)?, currency))
+    }
+
+    pub fn amount(&self) -> Millicents {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Fails for amounts of another currency and on overflow
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        if other.currency != self.currency {
+            return Err(MoneyError::CurrencyMismatch {
+                expected: self.currency,
+                actual: other.currency,
+            });
+        }
+        let amount = self
+            .amount
+            .checked_add(other.amount)
+            .ok_or(MoneyError::Amount(MillicentsError::Overflow))?;
+        Ok(Money::new(amount, self.currency))
+    }
+
+    /// Adds up amounts of `currency`, which is also the currency of the empty sum
+    pub fn total(
+        currency: Currency,
+        amounts: impl IntoIterator<Item = Money>,
+    ) -> Result<Money, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), Money::checked_add)
+    }
+
+    /// See [`Millicents::allocate`]
+    pub fn allocate(self, ratios: &[u32]) -> Option<Vec<Money>> {
+        let parts = self.amount.allocate(ratios)?;
+        Some(
+            parts
+                .into_iter()
+                .map(|part| Money::new(part, self.currency))
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.amount.format(&self.currency.format()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn currencies_have_codes() {
+        assert_eq!(Currency::from_code("CHF"), Some(Currency::Chf));
+        assert_eq!(Currency::from_code("eur"), Some(Currency::Eur));
+        assert_eq!(Currency::from_code(""), Some(Currency::Eur));
+        assert_eq!(Currency::from_code("USD"), None);
+        for currency in Currency::all() {
+            assert_eq!(Currency::from_code(currency.code()), Some(currency));
+        }
+    }
+
+    #[test]
+    fn formats_per_currency() {
+        let amount = Millicents::from_raw(1_050_000).unwrap();
+        assert_eq!(
+            Money::new(amount, Currency::Eur).to_string(),
+            "10.50\u{00a0}€"
+        );
+        assert_eq!(
+            Money::new(amount, Currency::Chf).to_string(),
+            "CHF\u{00a0}10.50"
+        );
+        for currency in Currency::all() {
+            let money = Money::new(amount, currency);
+            assert_eq!(Money::parse(&money.to_string(), currency), Ok(money));
+        }
+    }
+
+    #[test]
+    fn parses_in_the_expected_currency() {
+        let chf = |raw| Money::from_raw(raw, Currency::Chf).unwrap();
+        assert_eq!(Money::parse("CHF 3", Currency::Chf), Ok(chf(300_000)));
+        assert_eq!(Money::parse("4,20 CHF", Currency::Chf), Ok(chf(420_000)));
+        assert_eq!(Money::parse("4.20", Currency::Chf), Ok(chf(420_000)));
+        assert_eq!(
+            Money::parse("4.20 €", Currency::Chf),
+            Err(MoneyError::CurrencyMismatch {
+                expected: Currency::Chf,
+                actual: Currency::Eur
+            })
+        );
+        assert_eq!(
+            Money::parse("CHF 4.20", Currency::Eur),
+            Err(MoneyError::CurrencyMismatch {
+                expected: Currency::Eur,
+                actual: Currency::Chf
+            })
+        );
+        assert_eq!(
+            Money::parse("4.20 $", Currency::Eur),
+            Err(MoneyError::Amount(MillicentsError::ParseError))
+        );
+    }
+
+    #[test]
+    fn mixed_currencies_are_rejected() {
+        let eur = Money::from_raw(100_000, Currency::Eur).unwrap();
+        let chf = Money::from_raw(100_000, Currency::Chf).unwrap();
+        assert_eq!(
+            eur.checked_add(eur),
+            Money::from_raw(200_000, Currency::Eur)
+        );
+        assert_eq!(
+            eur.checked_add(chf),
+            Err(MoneyError::CurrencyMismatch {
+                expected: Currency::Eur,
+                actual: Currency::Chf
+            })
+        );
+        assert_eq!(
+            Money::total(Currency::Chf, []),
+            Ok(Money::zero(Currency::Chf))
+        );
+        assert!(Money::total(Currency::Chf, [chf, eur]).is_err());
+        assert_eq!(
+            chf.allocate(&[1, 1, 1]).unwrap(),
+            [33_334, 33_333, 33_333].map(|raw| Money::from_raw(raw, Currency::Chf).unwrap())
+        );
+    }
+}
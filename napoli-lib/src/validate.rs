@@ -0,0 +1,298 @@
+//! Rules for the user-provided fields of requests. The server enforces them, clients can check
+//! them before sending.
+//!
+//! Text is normalized before it is checked: leading and trailing whitespace is removed, and in
+//! food and buyer names every run of whitespace becomes a single space. Limits count characters,
+//! not bytes.
+//!
+//! A server may be configured with other [`Limits`] than the defaults clients check against.
+
+use crate::error_details::Reason;
+use crate::limits::MAX_STR_LEN;
+use crate::{Currency, Millicents, Money};
+
+/// Entries can't cost more than 10000 of their currency. Grouped as units, cents and millicents
+#[allow(clippy::inconsistent_digit_grouping)]
+pub const MAX_PRICE_IN_MILLICENTS: i64 = 10_000_00_000;
+
+/// How long texts and how high prices may be, [`MAX_STR_LEN`] and [`MAX_PRICE_IN_MILLICENTS`]
+/// by default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_chars: usize,
+    pub max_price_in_millicents: i64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_chars: MAX_STR_LEN,
+            max_price_in_millicents: MAX_PRICE_IN_MILLICENTS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    Empty,
+    TooLong { max_chars: usize },
+    InvalidPrice,
+    PriceTooHigh { max_price_in_millicents: i64 },
+    UnknownCurrency,
+}
+
+impl ValidationError {
+    pub fn reason(&self) -> Reason {
+        match self {
+            ValidationError::Empty => Reason::FieldEmpty,
+            ValidationError::TooLong { .. } => Reason::FieldTooLong,
+            ValidationError::InvalidPrice => Reason::InvalidPrice,
+            ValidationError::PriceTooHigh { .. } => Reason::PriceTooHigh,
+            ValidationError::UnknownCurrency => Reason::UnknownCurrency,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Empty => write!(f, "must not be empty"),
+            ValidationError::TooLong { max_chars } => {
+                write!(f, "must not be longer than {} characters", max_chars)
+            }
+            ValidationError::InvalidPrice => write!(f, "is not a valid price"),
+            ValidationError::PriceTooHigh {
+                max_price_in_millicents,
+            } => write!(
+                f,
+                "must not be more than {}",
+                max_price_in_millicents / 100_000
+            ),
+            ValidationError::UnknownCurrency => write!(f, "is not a supported currency"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+type Result<T> = std::result::Result<T, ValidationError>;
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl Limits {
+    fn within_limit(&self, text: String) -> Result<String> {
+        if text.chars().count() > self.max_chars {
+            return Err(ValidationError::TooLong {
+                max_chars: self.max_chars,
+            });
+        }
+        Ok(text)
+    }
+
+    fn name(&self, text: &str) -> Result<String> {
+        let text = collapse_whitespace(text);
+        if text.is_empty() {
+            return Err(ValidationError::Empty);
+        }
+        self.within_limit(text)
+    }
+
+    pub fn food(&self, food: &str) -> Result<Food> {
+        self.name(food).map(Food)
+    }
+
+    pub fn buyer(&self, buyer: &str) -> Result<Buyer> {
+        self.name(buyer).map(Buyer)
+    }
+
+    pub fn menu_url(&self, menu_url: &str) -> Result<MenuUrl> {
+        self.within_limit(menu_url.trim().to_owned()).map(MenuUrl)
+    }
+
+    pub fn price_from_millicents(&self, millicents: i64) -> Result<Price> {
+        let millicents = Millicents::from_raw(millicents)
+            .map_err(|_| ValidationError::InvalidPrice)?
+            .raw();
+        if millicents > self.max_price_in_millicents {
+            return Err(ValidationError::PriceTooHigh {
+                max_price_in_millicents: self.max_price_in_millicents,
+            });
+        }
+        Ok(Price(millicents))
+    }
+
+    /// See [`Price::from_human`]
+    pub fn price_from_human(&self, price: &str, currency: Currency) -> Result<Price> {
+        let money = Money::parse(price, currency).map_err(|_| ValidationError::InvalidPrice)?;
+        self.price_from_millicents(money.amount().raw())
+    }
+
+    pub fn price_from_euro_float(&self, euros: f64) -> Result<Price> {
+        let millicents =
+            Millicents::from_euro_float(euros).map_err(|_| ValidationError::InvalidPrice)?;
+        self.price_from_millicents(millicents.raw())
+    }
+}
+
+/// What somebody orders, e.g. "Pizza Bufala"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Food(String);
+
+impl Food {
+    pub fn new(food: &str) -> Result<Self> {
+        Limits::default().food(food)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// Who orders, and pays for, an entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Buyer(String);
+
+impl Buyer {
+    pub fn new(buyer: &str) -> Result<Self> {
+        Limits::default().buyer(buyer)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// Link to the menu of an order. Orders without one are fine, so it may be empty
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MenuUrl(String);
+
+impl MenuUrl {
+    pub fn new(menu_url: &str) -> Result<Self> {
+        Limits::default().menu_url(menu_url)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// Price of an entry, between 0 and [`MAX_PRICE_IN_MILLICENTS`] or the configured limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Price(i64);
+
+impl Price {
+    pub fn from_millicents(millicents: i64) -> Result<Self> {
+        Limits::default().price_from_millicents(millicents)
+    }
+
+    /// Prices as typed by humans, e.g. "10.50" or "CHF 10.50". Amounts in other currencies than
+    /// `currency` are invalid
+    pub fn from_human(price: &str, currency: Currency) -> Result<Self> {
+        Limits::default().price_from_human(price, currency)
+    }
+
+    pub fn from_euro_float(euros: f64) -> Result<Self> {
+        Limits::default().price_from_euro_float(euros)
+    }
+
+    pub fn millicents(&self) -> i64 {
+        self.0
+    }
+}
+
+/// ISO 4217 code of a currency, empty for euros
+pub fn currency(code: &str) -> Result<Currency> {
+    Currency::from_code(code).ok_or(ValidationError::UnknownCurrency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(
+            Food::new("  Pizza \t Bufala\n").unwrap().as_str(),
+            "Pizza Bufala"
+        );
+        assert_eq!(Buyer::new(" \n "), Err(ValidationError::Empty));
+        assert_eq!(
+            MenuUrl::new(" https://napoli.example ").unwrap().as_str(),
+            "https://napoli.example"
+        );
+        assert_eq!(MenuUrl::new("").unwrap().as_str(), "");
+        assert_eq!(currency(" chf "), Ok(Currency::Chf));
+        assert_eq!(currency("USD"), Err(ValidationError::UnknownCurrency));
+    }
+
+    #[test]
+    fn limits_count_characters() {
+        let umlauts = "ü".repeat(MAX_STR_LEN);
+        assert!(umlauts.len() > MAX_STR_LEN);
+        assert!(Buyer::new(&umlauts).is_ok());
+        assert_eq!(
+            Buyer::new(&format!("{}ü", umlauts)),
+            Err(ValidationError::TooLong {
+                max_chars: MAX_STR_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn limits_can_be_changed() {
+        let limits = Limits {
+            max_chars: 5,
+            max_price_in_millicents: 20 * 100_000,
+        };
+        assert!(limits.food("Pizza").is_ok());
+        let too_long = limits.buyer("Roberta").unwrap_err();
+        assert_eq!(too_long, ValidationError::TooLong { max_chars: 5 });
+        assert_eq!(too_long.to_string(), "must not be longer than 5 characters");
+        let too_high = limits.price_from_human("20.01", Currency::Eur).unwrap_err();
+        assert_eq!(too_high.to_string(), "must not be more than 20");
+        assert!(Price::from_human("20.01", Currency::Eur).is_ok());
+    }
+
+    #[test]
+    fn prices_are_bounded() {
+        assert_eq!(
+            Price::from_human("10.50", Currency::Eur)
+                .unwrap()
+                .millicents(),
+            1_050_000
+        );
+        assert_eq!(
+            Price::from_human("ten", Currency::Eur),
+            Err(ValidationError::InvalidPrice)
+        );
+        assert_eq!(
+            Price::from_human("10.50 €", Currency::Chf),
+            Err(ValidationError::InvalidPrice)
+        );
+        assert_eq!(
+            Price::from_millicents(-1),
+            Err(ValidationError::InvalidPrice)
+        );
+        assert!(Price::from_millicents(MAX_PRICE_IN_MILLICENTS).is_ok());
+        assert_eq!(
+            Price::from_millicents(MAX_PRICE_IN_MILLICENTS + 1),
+            Err(ValidationError::PriceTooHigh {
+                max_price_in_millicents: MAX_PRICE_IN_MILLICENTS
+            })
+        );
+    }
+}
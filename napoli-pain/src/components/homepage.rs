@@ -7,7 +7,7 @@ use crate::components::order_list::OrderList;
 pub enum Msg {
     GotOrders(Vec<npb::Order>),
     OrderFetchFailed(service::ServiceError),
-    AddOrder(String),
+    AddOrder(String, napoli_lib::Currency),
 }
 #[derive(Clone)]
 pub enum FetchOrdersState {
@@ -30,7 +30,7 @@ impl Component for Homepage {
     type Properties = AppConfigProps;
 
     fn create(ctx: &Context<Self>) -> Self {
-        let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+        let mut svc = service::Napoli::new(crate::backend_url().to_owned());
         ctx.link().send_future(async move {
             match svc.get_orders().await {
                 Ok(orders) => Msg::GotOrders(orders),
@@ -52,11 +52,11 @@ impl Component for Homepage {
                 self.orders = FetchOrdersState::Failed(e);
                 true
             }
-            Msg::AddOrder(menu_url) => {
-                let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+            Msg::AddOrder(menu_url, currency) => {
+                let mut svc = service::Napoli::new(crate::backend_url().to_owned());
                 let orders = self.orders.clone();
                 _ctx.link().send_future(async move {
-                    match svc.create_order(menu_url).await {
+                    match svc.create_order(menu_url, currency).await {
                         Ok(order) => match orders {
                             FetchOrdersState::Got(orders) => {
                                 let mut orders = orders;
@@ -75,7 +75,9 @@ impl Component for Homepage {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let on_create_new_order = ctx.link().callback(Msg::AddOrder);
+        let on_create_new_order = ctx
+            .link()
+            .callback(|(menu_url, currency)| Msg::AddOrder(menu_url, currency));
 
         match &self.orders {
             FetchOrdersState::Fetching => html! {
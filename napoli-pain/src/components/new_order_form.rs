@@ -1,17 +1,37 @@
+use napoli_lib::validate::MenuUrl;
+use napoli_lib::Currency;
 use yew::prelude::*;
 
 #[derive(PartialEq, Properties)]
 pub struct NewOrderFormProps {
-    pub onclick: Callback<String>,
+    pub onclick: Callback<(String, Currency)>,
 }
 
 #[function_component(NewOrderForm)]
 pub fn new_order_form(props: &NewOrderFormProps) -> Html {
     let menu_url = use_state(|| "".to_string());
+    let currency = use_state(Currency::default);
 
-    let mu_clone = menu_url.trim().to_owned();
+    let checked_menu_url = MenuUrl::new(&menu_url);
+    let is_form_valid = checked_menu_url.is_ok();
+    let mu_clone = checked_menu_url
+        .map(MenuUrl::into_inner)
+        .unwrap_or_default();
 
-    let onclick = props.onclick.reform(move |_| mu_clone.clone());
+    let selected_currency = *currency;
+    let onclick = props
+        .onclick
+        .reform(move |_| (mu_clone.clone(), selected_currency));
+    let currency_options = Currency::all()
+        .into_iter()
+        .map(|option| {
+            html! {
+                <option value={option.code()} selected={option == selected_currency}>
+                    { option.code() }
+                </option>
+            }
+        })
+        .collect::<Html>();
     html! {
         <form class="my-8" onsubmit={move |e: SubmitEvent| { e.prevent_default() }}>
             <label for="menu_url" class="mr-4">{"Menu URL:"}</label>
@@ -26,12 +46,24 @@ pub fn new_order_form(props: &NewOrderFormProps) -> Html {
                     let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
                     menu_url.set(input.value());
                 }}
-                class="textinput"
+                class={if is_form_valid { "textinput" } else { "textinput-invalid" }}
                 />
+            <label for="currency" class="mx-4">{"Currency:"}</label>
+            <select
+                id="currency"
+                name="currency"
+                class="textinput mr-4"
+                onchange={move |e: Event| {
+                    let select = e.target_unchecked_into::<web_sys::HtmlSelectElement>();
+                    currency.set(Currency::from_code(&select.value()).unwrap_or_default());
+                }}>
+                { currency_options }
+            </select>
             <input
                 type="submit"
                 value="Open new order"
                 class="btn"
+                disabled={!is_form_valid}
                 {onclick}/>
         </form>
     }
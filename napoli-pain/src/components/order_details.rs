@@ -10,6 +10,7 @@ use crate::{
 };
 use futures::StreamExt;
 use napoli_lib::napoli::{self as npb, ObjectId, SingleOrderReply};
+use napoli_lib::{Currency, Money};
 use yew::prelude::*;
 use yew_router::prelude::Link;
 
@@ -24,7 +25,7 @@ pub struct OrderDetailsProps {
 pub struct OrderDetails {
     order: Option<npb::Order>,
     live_streaming_status: LiveStreamingStatus,
-    error: Option<String>,
+    error: Option<service::ServiceError>,
 }
 
 pub enum OrderDetailsMsg {
@@ -46,7 +47,7 @@ impl Component for OrderDetails {
     type Properties = OrderDetailsProps;
 
     fn create(ctx: &Context<Self>) -> Self {
-        let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+        let mut svc = service::Napoli::new(crate::backend_url().to_owned());
 
         ctx.link().send_future(async move {
             match svc.get_orders().await {
@@ -55,7 +56,7 @@ impl Component for OrderDetails {
             }
         });
 
-        let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+        let mut svc = service::Napoli::new(crate::backend_url().to_owned());
         let id = ctx.props().id;
         ctx.link().send_future(async move {
             let res = svc.stream_order_updates(id).await;
@@ -84,10 +85,15 @@ impl Component for OrderDetails {
             }
             Self::Message::OrderFetchFailed(_e) => false,
             Self::Message::SetOrderEntryPaid { entry_id, paid } => {
-                let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+                let mut svc = service::Napoli::new(crate::backend_url().to_owned());
                 let order_id = ctx.props().id;
+                // Don't undo what somebody else did since we last got the order
+                let expected_version = self.order.as_ref().map(|order| order.version);
                 ctx.link().send_future(async move {
-                    match svc.set_order_entry_paid(order_id, entry_id, paid).await {
+                    match svc
+                        .set_order_entry_paid(order_id, entry_id, paid, expected_version)
+                        .await
+                    {
                         Ok(order) => Self::Message::GotOrderUpdated(order),
                         Err(e) => Self::Message::OrderFetchFailed(e), // This is fine 🔥
                     }
@@ -95,13 +101,15 @@ impl Component for OrderDetails {
                 false
             }
             Self::Message::RemoveOrderEntry { entry_id } => {
-                let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+                let mut svc = service::Napoli::new(crate::backend_url().to_owned());
                 let order_id = ctx.props().id;
+                let expected_version = self.order.as_ref().map(|order| order.version);
                 ctx.link().send_future(async move {
                     match svc
                         .remove_order_entry(npb::OrderEntryRequest {
                             order_id,
                             order_entry_id: entry_id,
+                            expected_version,
                         })
                         .await
                     {
@@ -112,7 +120,7 @@ impl Component for OrderDetails {
                 false
             }
             OrderDetailsMsg::AddOrderEntry(add_order_entry_request) => {
-                let mut svc = service::Napoli::new(crate::BACKEND_URL.to_string());
+                let mut svc = service::Napoli::new(crate::backend_url().to_owned());
                 ctx.link().send_future(async move {
                     match svc.add_order_entry(add_order_entry_request).await {
                         Ok(order) => Self::Message::GotOrderUpdated(order),
@@ -122,11 +130,12 @@ impl Component for OrderDetails {
                 false
             }
             Self::Message::AddOrderFailed(e) => {
-                self.error = Some(format!("{e:?}"));
+                self.error = Some(e);
                 true
             }
             Self::Message::GotOrderUpdated(order) => {
                 self.order = Some(order);
+                self.error = None;
                 true
             }
             Self::Message::StreamingConnected(stream) => {
@@ -162,6 +171,8 @@ impl Component for OrderDetails {
                 },
             );
 
+            // Unknown currencies are shown as euros until the client knows them
+            let currency = Currency::from_code(&order.currency).unwrap_or_default();
             let order_entries =
                 order
                     .entries
@@ -176,30 +187,38 @@ impl Component for OrderDetails {
                             .callback(|entry_id| Self::Message::RemoveOrderEntry { entry_id });
                         html! {
                             <li style="list-style: none">
-                                <OrderEntry {order_entry} {on_paid_clicked} {on_remove_clicked}/>
+                                <OrderEntry {order_entry} {currency} {on_paid_clicked} {on_remove_clicked}/>
                             </li>
                         }
                     })
                     .collect::<Vec<_>>();
 
-            let total_millicents: Option<i64> = order
+            let prices: Result<Vec<_>, _> = order
                 .entries
                 .iter()
-                .map(|order| order.price_in_millicents)
-                .try_fold(0i64, |left, right| left.checked_add(right));
+                .filter(|entry| !entry.is_tainted())
+                .map(|entry| Money::from_raw(entry.price_in_millicents, currency))
+                .collect();
 
-            let total_str = match total_millicents.map(napoli_lib::Millicents::from_raw) {
-                Some(Ok(price)) => {
-                    let (euros, cents) = price.to_euro_tuple();
-                    format!("{}.{:02}\u{00a0}€", euros, cents)
+            let total_str = match prices.and_then(|prices| Money::total(currency, prices)) {
+                Ok(total) if order.is_tainted() => {
+                    format!("{} (probably wrong, see the warnings above)", total)
                 }
-                Some(Err(e)) => {
-                    format!(
-                        "Invalid price value: {:?}; Error: {:?}",
-                        total_millicents, e
-                    )
+                Ok(total) => total.to_string(),
+                Err(e) => format!("Invalid total; Error: {}", e),
+            };
+
+            let integrity_warnings = if order.is_tainted() {
+                html! {
+                    <div class="mt-4 text-red-700">
+                        <p>{"The stored order contains broken data:"}</p>
+                        <ul class="list-disc ml-6">
+                        { for order.integrity_warnings.iter().map(|warning| html! { <li>{warning}</li> }) }
+                        </ul>
+                    </div>
                 }
-                None => "Error: Total sum overflowed".to_string(),
+            } else {
+                html!()
             };
 
             let id = order.id;
@@ -208,7 +227,7 @@ impl Component for OrderDetails {
 
             let error_toast_maybe = if let Some(error) = self.error.as_ref() {
                 html! {
-                    <crate::components::toast::Toast message={format!("Error: {error:?}")} kind={crate::components::toast::ToastKind::Error} />
+                    <crate::components::toast::Toast message={format!("Error: {}", error.message())} kind={crate::components::toast::ToastKind::Error} />
                 }
             } else {
                 html!()
@@ -220,11 +239,12 @@ impl Component for OrderDetails {
                     <Link<Route> to={Route::Home} classes="btn"> {"< Back"} </Link<Route>>
                     <h1 class="mt-8">{"Order #"}{id}</h1>
                     <p>{"Menu URL: "}<a class="link" href={menu_url} target="_blank" rel="noopener noreferrer">{menu_url_text}</a></p>
+                    {integrity_warnings}
 
                     <ul class="mt-4">
                     { order_entries }
                     </ul>
-                    <AddOrderEntryForm order_id={order.id} onclick={on_add_new_order_request} />
+                    <AddOrderEntryForm order_id={order.id} {currency} onclick={on_add_new_order_request} error={self.error.clone()} />
                     <OrderSummary order_entries={order.entries.clone()} />
                     <StreamingIndicator status={self.live_streaming_status.clone()} />
                     <p>{"Total: "}{total_str}</p>
@@ -247,6 +267,7 @@ pub struct OrderEntry {}
 #[derive(PartialEq, Properties)]
 pub struct OrderEntryProps {
     pub order_entry: npb::OrderEntry,
+    pub currency: Currency,
     pub on_paid_clicked: Callback<(ObjectId, bool)>,
     pub on_remove_clicked: Callback<ObjectId>,
 }
@@ -275,13 +296,10 @@ impl Component for OrderEntry {
         let left_style = "padding-right: 1em; text-align: right;";
         let tr_style = "";
 
-        let price_str = match napoli_lib::Millicents::from_raw(entry.price_in_millicents) {
-            Ok(price) => {
-                let (euros, cents) = price.to_euro_tuple();
-                format!("{}.{:02}\u{00a0}€", euros, cents)
-            }
+        let price_str = match Money::from_raw(entry.price_in_millicents, ctx.props().currency) {
+            Ok(price) => price.to_string(),
             Err(e) => format!(
-                "Invalid price value: {}; Error: {:?}",
+                "Invalid price value: {}; Error: {}",
                 entry.price_in_millicents, e
             ),
         };
@@ -291,6 +309,12 @@ impl Component for OrderEntry {
                 <tr style={tr_style}><td style={left_style}>{"Person"}</td><td>{&entry.buyer}</td></tr>
                 <tr style={tr_style}><td style={left_style}>{"Price"}</td><td>{price_str}</td></tr>
                 <tr style={tr_style}><td style={left_style}>{"Food"}</td><td>{&entry.food}</td></tr>
+                if entry.is_tainted() {
+                    <tr style={tr_style} class="text-red-700">
+                        <td style={left_style}>{"Broken"}</td>
+                        <td>{entry.integrity_warnings.join(", ")}</td>
+                    </tr>
+                }
                 <tr style={tr_style}>
                     <td style={left_style}>{"Paid"}</td>
                     <td>
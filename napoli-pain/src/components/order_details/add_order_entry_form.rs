@@ -1,30 +1,71 @@
 use napoli_lib::napoli::ObjectId;
+use napoli_lib::validate::{Buyer, Food, Price, ValidationError};
+use napoli_lib::Currency;
 use yew::prelude::*;
 
+use crate::service::ServiceError;
+
 #[derive(PartialEq, Properties)]
 pub struct AddOrderEntryFormProps {
     pub order_id: ObjectId,
+    /// Prices are entered in the currency of the order
+    pub currency: Currency,
     pub onclick: Callback<napoli_lib::napoli::AddOrderEntryRequest>,
+    /// The fields the server rejected are highlighted
+    #[prop_or_default]
+    pub error: Option<ServiceError>,
+}
+
+fn input_class(violation: &Option<String>) -> &'static str {
+    match violation {
+        Some(_) => "textinput-invalid ml-2",
+        None => "textinput ml-2",
+    }
+}
+
+fn violation_hint(violation: &Option<String>) -> Html {
+    match violation {
+        Some(description) => html! { <p class="text-sm text-red-700">{ description }</p> },
+        None => html!(),
+    }
 }
 
 #[function_component(AddOrderEntryForm)]
 pub fn add_order_entry_form(props: &AddOrderEntryFormProps) -> Html {
     let order_id = props.order_id;
+    let currency = props.currency;
     let food = use_state(|| "".to_string());
     let buyer = use_state(|| "".to_string());
     let price = use_state(|| "".to_string());
 
-    let price_mc = napoli_lib::Millicents::from_euro_human(&price);
+    let checked_food = Food::new(&food);
+    let checked_buyer = Buyer::new(&buyer);
+    let checked_price = Price::from_human(&price, currency);
+    let is_form_valid = checked_food.is_ok() && checked_buyer.is_ok() && checked_price.is_ok();
 
-    let is_food_valid = food.len() >= 2;
-    let is_buyer_valid = buyer.len() >= 2;
-    let is_price_valid = price_mc.is_ok();
-    let is_form_valid = is_food_valid && is_buyer_valid && is_price_valid;
+    // Fields are checked as soon as something was typed, the server has the last word
+    let violation = |field: &str, input: &str, checked: Option<ValidationError>| {
+        let rejected = props
+            .error
+            .as_ref()
+            .and_then(|error| error.violation(field))
+            .map(str::to_owned);
+        let invalid = checked
+            .filter(|_| !input.is_empty())
+            .map(|err| err.to_string());
+        rejected.or(invalid)
+    };
+    let food_violation = violation("food", &food, checked_food.as_ref().err().cloned());
+    let buyer_violation = violation("buyer", &buyer, checked_buyer.as_ref().err().cloned());
+    let price_violation = violation(
+        "price_in_millicents",
+        &price,
+        checked_price.as_ref().err().cloned(),
+    );
 
-    let food_str = food.trim().to_string();
-    let buyer_str = buyer.trim().to_string();
-
-    let millicents = price_mc.map(|v| v.raw()).unwrap_or(0);
+    let food_str = checked_food.map(Food::into_inner).unwrap_or_default();
+    let buyer_str = checked_buyer.map(Buyer::into_inner).unwrap_or_default();
+    let millicents = checked_price.map(|price| price.millicents()).unwrap_or(0);
 
     html! {
         <div class="pt-8">
@@ -34,10 +75,9 @@ pub fn add_order_entry_form(props: &AddOrderEntryFormProps) -> Html {
                 <label for="food">{"Food:"}</label>
                 <input
                     id="food"
-                    class="textinput ml-2"
+                    class={input_class(&food_violation)}
                     name="food"
                     type="text"
-                    minlength=2
                     maxlength=210
                     placeholder="Food"
                     required=true
@@ -46,16 +86,16 @@ pub fn add_order_entry_form(props: &AddOrderEntryFormProps) -> Html {
                         let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
                         food.set(input.value());
                     }}/>
+                { violation_hint(&food_violation) }
                 </div>
                 <div class="mb-1">
                     <label for="buyer">{"Buyer:"}</label>
                     <input
                         id="buyer"
-                        class="textinput ml-2"
+                        class={input_class(&buyer_violation)}
                         name="buyer"
                         type="text"
-                        minlength=2
-                        maxlength=210
+                            maxlength=210
                         placeholder="Buyer"
                         required=true
                         value={buyer.to_string()}
@@ -63,22 +103,24 @@ pub fn add_order_entry_form(props: &AddOrderEntryFormProps) -> Html {
                             let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
                             buyer.set(input.value());
                         }}/>
+                    { violation_hint(&buyer_violation) }
                 </div>
                 <div class="mb-2">
                     <label for="price">{"Price:"}</label>
                     <input
                         id="price"
-                        class="textinput ml-2"
+                        class={input_class(&price_violation)}
                         name="price"
-                        type="number"
-                        step="0.01"
-                        placeholder="Price"
+                        type="text"
+                        inputmode="decimal"
+                        placeholder={format!("Price in {}", currency.symbol())}
                         required=true
                         value={price.to_string()}
                         oninput={move |e: InputEvent| {
                             let input = e.target_unchecked_into::<web_sys::HtmlInputElement>();
                             price.set(input.value());
                         }}/>
+                    { violation_hint(&price_violation) }
                 </div>
                 <input
                     type="submit"
@@ -91,6 +133,8 @@ pub fn add_order_entry_form(props: &AddOrderEntryFormProps) -> Html {
                         buyer: buyer_str.clone(),
                         price_deprecated: 0.0,
                         price_in_millicents: millicents,
+                        expected_version: None,
+                        currency: currency.code().to_owned(),
                 })}/>
             </form>
         </div>
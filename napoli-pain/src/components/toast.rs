@@ -25,7 +25,7 @@ pub fn Toast(props: &Props) -> Html {
             <div class="ps-4 text-sm font-normal">{props.message.clone()}</div>
             </div>
         ),
-        modal_host.into(),
+        modal_host,
     )
 }
 
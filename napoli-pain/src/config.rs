@@ -0,0 +1,25 @@
+//! `/config.json`, which napoli-server serves next to the frontend to say where its APIs are
+
+use wasm_bindgen::JsValue;
+
+/// The backend named in `/config.json`, or the page's own origin if the file names none. Nothing
+/// if there is no such file, e.g. with `trunk serve`
+pub async fn backend_url() -> Option<String> {
+    let response = gloo::net::http::Request::get("/config.json")
+        .send()
+        .await
+        .ok()?;
+    if !response.ok() {
+        return None;
+    }
+    // Dev servers may answer with index.html instead, which doesn't parse
+    let config = js_sys::JSON::parse(&response.text().await.ok()?).ok()?;
+    if !config.is_object() {
+        return None;
+    }
+    let backend_url = js_sys::Reflect::get(&config, &JsValue::from_str("backendUrl")).ok()?;
+    match backend_url.as_string() {
+        Some(backend_url) => Some(backend_url),
+        None => gloo::utils::window().location().origin().ok(),
+    }
+}
@@ -1,14 +1,33 @@
+use std::sync::OnceLock;
+
 mod components;
+mod config;
 mod router;
 mod service;
 
-pub const BACKEND_URL: &str = match option_env!("BACKEND_URL") {
+/// The backend if the server of the frontend doesn't name one in `/config.json`
+const DEFAULT_BACKEND_URL: &str = match option_env!("BACKEND_URL") {
     Some(url) => url,
     None => "http://[::1]:50051",
 };
 
+static BACKEND_URL: OnceLock<String> = OnceLock::new();
+
+/// Where the APIs are, as found out when the frontend started
+pub fn backend_url() -> &'static str {
+    BACKEND_URL
+        .get()
+        .map_or(DEFAULT_BACKEND_URL, String::as_str)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    yew::Renderer::<router::Router>::default().render();
+    yew::platform::spawn_local(async {
+        let backend_url = config::backend_url()
+            .await
+            .unwrap_or_else(|| DEFAULT_BACKEND_URL.to_owned());
+        let _ = BACKEND_URL.set(backend_url);
+        yew::Renderer::<router::Router>::default().render();
+    });
 
     Ok(())
 }
@@ -1,8 +1,8 @@
+use crate::backend_url;
 use crate::components::homepage::Homepage;
 use crate::components::order_details::OrderDetails;
 use crate::components::server_name::ServerName;
 use crate::components::toast::ToastHost;
-use crate::BACKEND_URL;
 
 use napoli_lib::napoli::ObjectId;
 use yew::prelude::*;
@@ -18,7 +18,7 @@ pub enum Route {
 
 fn switch(routes: Route) -> Html {
     match routes {
-        Route::Home => html! { <Homepage backend_url={BACKEND_URL} /> },
+        Route::Home => html! { <Homepage backend_url={backend_url()} /> },
         Route::OrderListEntry { id } => html! {
             <OrderDetails id={id} />
         },
@@ -30,7 +30,7 @@ pub fn app() -> Html {
     html! {
         <div class="m-4 font-mono">
             <ToastHost />
-            <ServerName name={BACKEND_URL} />
+            <ServerName name={backend_url()} />
             <BrowserRouter>
                 <Switch<Route> render={switch} /> // <- must be child of <BrowserRouter>
             </BrowserRouter>
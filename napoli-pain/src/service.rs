@@ -1,28 +1,47 @@
 use tonic_web_wasm_client::Client;
 use yew::prelude::*;
 
+use napoli_lib::error_details::ErrorDetails;
 use napoli_lib::napoli as npb;
 
-#[derive(Debug, Clone)]
-pub struct ServiceError(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceError {
+    message: String,
+    details: ErrorDetails,
+}
 
 impl ServiceError {
     pub fn html(&self) -> Html {
         html! {
-            { self.0.clone() }
+            { self.message.clone() }
         }
     }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Why the server rejected `field` of the request, if it did
+    pub fn violation(&self, field: &str) -> Option<&str> {
+        self.details.violation(field)
+    }
 }
 
 impl From<tonic::Status> for ServiceError {
     fn from(other: tonic::Status) -> Self {
-        ServiceError(other.message().into())
+        ServiceError {
+            message: other.message().into(),
+            details: ErrorDetails::from_status(&other),
+        }
     }
 }
 
 impl From<&str> for ServiceError {
     fn from(other: &str) -> Self {
-        ServiceError(other.into())
+        ServiceError {
+            message: other.into(),
+            details: ErrorDetails::default(),
+        }
     }
 }
 
@@ -49,6 +68,7 @@ impl Napoli {
         order_id: npb::ObjectId,
         order_entry_id: npb::ObjectId,
         paid: bool,
+        expected_version: Option<i32>,
     ) -> Result<npb::Order> {
         let order = self
             .client
@@ -56,15 +76,23 @@ impl Napoli {
                 order_id,
                 order_entry_id,
                 paid,
+                expected_version,
             })
             .await?;
         Ok(order.into_inner().order.expect("fucked up"))
     }
 
-    pub async fn create_order(&mut self, menu_url: String) -> Result<npb::Order> {
+    pub async fn create_order(
+        &mut self,
+        menu_url: String,
+        currency: napoli_lib::Currency,
+    ) -> Result<npb::Order> {
         let order = self
             .client
-            .create_order(npb::CreateOrderRequest { menu_url })
+            .create_order(npb::CreateOrderRequest {
+                menu_url,
+                currency: currency.code().to_owned(),
+            })
             .await?;
         Ok(order.into_inner().order.expect("fucked up"))
     }
@@ -6,6 +6,13 @@ mod m20230206_005235_order_entry_add_price;
 mod m20230425_2051_price;
 mod m20241126_202903_add_date_to_order;
 mod m20250203_200826_throw_away_long_strings;
+mod m20261019_073000_create_order_change;
+mod m20261019_083000_add_version_to_order;
+mod m20261019_093000_add_currency_to_order;
+mod m20261019_103000_order_created_at;
+mod m20261019_113000_repair_order_state;
+mod m20261019_123000_cascade_order_entries;
+pub mod verify;
 
 pub struct Migrator;
 
@@ -19,6 +26,12 @@ impl MigratorTrait for Migrator {
             Box::new(m20230425_2051_price::Migration),
             Box::new(m20241126_202903_add_date_to_order::Migration),
             Box::new(m20250203_200826_throw_away_long_strings::Migration),
+            Box::new(m20261019_073000_create_order_change::Migration),
+            Box::new(m20261019_083000_add_version_to_order::Migration),
+            Box::new(m20261019_093000_add_currency_to_order::Migration),
+            Box::new(m20261019_103000_order_created_at::Migration),
+            Box::new(m20261019_113000_repair_order_state::Migration),
+            Box::new(m20261019_123000_cascade_order_entries::Migration),
         ]
     }
 }
@@ -1,4 +1,5 @@
 use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
@@ -21,6 +22,17 @@ enum OrderEntry {
     Paid,
 }
 
+/// An auto incremented id. Postgres only auto increments signed integers, SQLite writes both as
+/// `integer`
+fn id_column(manager: &SchemaManager, id: impl IntoIden) -> ColumnDef {
+    let mut column = ColumnDef::new(id);
+    match manager.get_database_backend() {
+        DatabaseBackend::Postgres => column.integer(),
+        _ => column.unsigned(),
+    };
+    column.not_null().auto_increment().primary_key().to_owned()
+}
+
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
@@ -28,13 +40,7 @@ impl MigrationTrait for Migration {
             .create_table(
                 Table::create()
                     .table(Order::Table)
-                    .col(
-                        ColumnDef::new(Order::Id)
-                            .unsigned()
-                            .not_null()
-                            .auto_increment()
-                            .primary_key(),
-                    )
+                    .col(&mut id_column(manager, Order::Id))
                     .col(ColumnDef::new(Order::MenuUrl).text().not_null())
                     .col(
                         ColumnDef::new(Order::OrderState)
@@ -50,13 +56,7 @@ impl MigrationTrait for Migration {
             .create_table(
                 Table::create()
                     .table(OrderEntry::Table)
-                    .col(
-                        ColumnDef::new(OrderEntry::Id)
-                            .unsigned()
-                            .not_null()
-                            .auto_increment()
-                            .primary_key(),
-                    )
+                    .col(&mut id_column(manager, OrderEntry::Id))
                     .col(ColumnDef::new(OrderEntry::OrderId).unsigned().not_null())
                     .col(ColumnDef::new(OrderEntry::Buyer).text().not_null())
                     .col(ColumnDef::new(OrderEntry::Food).text().not_null())
@@ -80,6 +80,16 @@ impl MigrationTrait for Migration {
     }
 
     async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            // Postgres refuses to drop orders while entries reference them
+            manager
+                .drop_table(Table::drop().table(OrderEntry::Table).to_owned())
+                .await?;
+            return manager
+                .drop_table(Table::drop().table(Order::Table).to_owned())
+                .await;
+        }
+
         manager
             .drop_table(Table::drop().table(Order::Table).to_owned())
             .await?;
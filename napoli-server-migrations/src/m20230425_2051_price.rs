@@ -1,4 +1,5 @@
 use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
 
 #[derive(DeriveMigrationName)]
 pub struct Migration;
@@ -33,7 +34,12 @@ impl MigrationTrait for Migration {
             .await
             .expect("Failed to add price_in_millicents column.");
 
-        // Fill priceinmillicents from price column
+        // Fill priceinmillicents from price column. A Postgres integer only holds 21474 euros,
+        // in SQLite every integer has 64 bits
+        let millicents_type = match manager.get_database_backend() {
+            DatabaseBackend::Postgres => Alias::new("bigint"),
+            _ => Alias::new("integer"),
+        };
         manager
             .exec_stmt(
                 Query::update()
@@ -42,7 +48,7 @@ impl MigrationTrait for Migration {
                         OrderEntry::PriceInMillicents,
                         Expr::col(OrderEntry::Price)
                             .mul(100000)
-                            .cast_as(Alias::new("integer")),
+                            .cast_as(millicents_type),
                     )])
                     .to_owned(),
             )
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/* Migration Purpose:
+ * Log changed orders with an increasing sequence number, so server instances sharing the
+ * database can poll for changes made by the others
+ */
+
+#[derive(Iden)]
+enum OrderChange {
+    Table,
+    Seq,
+    OrderId,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(OrderChange::Table)
+                    .col(
+                        ColumnDef::new(OrderChange::Seq)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(OrderChange::OrderId).integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(OrderChange::Table).to_owned())
+            .await
+    }
+}
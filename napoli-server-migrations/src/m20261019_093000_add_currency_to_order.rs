@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/* Migration Purpose:
+ * Orders can be in other currencies than euros now. All existing orders are in euros
+ */
+
+#[derive(Iden)]
+enum Order {
+    Table,
+    Currency,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Order::Table)
+                    .add_column(
+                        ColumnDef::new(Order::Currency)
+                            .string()
+                            .not_null()
+                            .default("EUR"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Order::Table)
+                    .drop_column(Order::Currency)
+                    .to_owned(),
+            )
+            .await
+    }
+}
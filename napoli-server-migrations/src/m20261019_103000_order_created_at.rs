@@ -0,0 +1,142 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::ConnectionTrait;
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/* Migration Purpose:
+ * Replace the RFC 3339 text in order.timestamp by a real datetime column, order.created_at.
+ *
+ * Orders from before m20241126_202903_add_date_to_order have no timestamp. Ids are handed out in
+ * the order orders are created, so they get the time of the next newer order that has one, which
+ * is a little too late at most. Without a newer order, the time of this migration is the best
+ * guess.
+ *
+ * Times are stored in UTC and whole seconds, like the server writes them, because SQLite compares
+ * them as text
+ */
+
+#[derive(Iden)]
+enum Order {
+    Table,
+    Id,
+    Timestamp,
+    CreatedAt,
+}
+
+fn normalized(time: OffsetDateTime) -> OffsetDateTime {
+    time.to_offset(UtcOffset::UTC)
+        .replace_nanosecond(0)
+        .expect("0 is a valid nanosecond")
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Order::Table)
+                    .add_column(
+                        ColumnDef::new(Order::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            // SQLite only adds columns with constant defaults, all rows are
+                            // backfilled below
+                            .default("1970-01-01T00:00:00Z"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        let orders = db
+            .query_all(
+                db.get_database_backend().build(
+                    Query::select()
+                        .columns([Order::Id, Order::Timestamp])
+                        .from(Order::Table)
+                        .order_by(Order::Id, sea_orm_migration::sea_query::Order::Desc),
+                ),
+            )
+            .await?;
+
+        let mut next_newer = normalized(OffsetDateTime::now_utc());
+        for order in orders {
+            let id: i32 = order.try_get("", "id")?;
+            let timestamp: Option<String> = order.try_get("", "timestamp")?;
+            let created_at = timestamp
+                .and_then(|timestamp| OffsetDateTime::parse(&timestamp, &Rfc3339).ok())
+                .map(normalized)
+                .unwrap_or(next_newer);
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(Order::Table)
+                        .value(Order::CreatedAt, created_at)
+                        .and_where(Expr::col(Order::Id).eq(id))
+                        .to_owned(),
+                )
+                .await?;
+            next_newer = created_at;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Order::Table)
+                    .drop_column(Order::Timestamp)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Order::Table)
+                    .add_column(ColumnDef::new(Order::Timestamp).text())
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        let orders = db
+            .query_all(
+                db.get_database_backend().build(
+                    Query::select()
+                        .columns([Order::Id, Order::CreatedAt])
+                        .from(Order::Table),
+                ),
+            )
+            .await?;
+        for order in orders {
+            let id: i32 = order.try_get("", "id")?;
+            let created_at: OffsetDateTime = order.try_get("", "created_at")?;
+            let timestamp = created_at
+                .format(&Rfc3339)
+                .map_err(|err| DbErr::Custom(err.to_string()))?;
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(Order::Table)
+                        .value(Order::Timestamp, timestamp)
+                        .and_where(Expr::col(Order::Id).eq(id))
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Order::Table)
+                    .drop_column(Order::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/* Migration Purpose:
+ * order.state is read as an enum of the states 1 (open), 2 (closed) and 3 (done) now, so rows
+ * with other numbers would fail to load. Close them: a closed order takes no more entries, but
+ * isn't hidden as done either, and can be reopened by hand. The server closes imported orders
+ * with unknown states the same way
+ */
+
+const CLOSED: i32 = 2;
+const STATES: [i32; 3] = [1, 2, 3];
+
+#[derive(Iden)]
+enum Order {
+    Table,
+    State,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Order::Table)
+                    .value(Order::State, CLOSED)
+                    .and_where(Expr::col(Order::State).is_not_in(STATES))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // The broken states are gone
+        Ok(())
+    }
+}
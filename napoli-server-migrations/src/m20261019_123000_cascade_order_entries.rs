@@ -0,0 +1,266 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{DatabaseBackend, RuntimeErr};
+use sea_orm_migration::SchemaManagerConnection;
+use sqlx::{Connection, SqliteConnection};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/* Migration Purpose:
+ * Entries belong to their order: deleting an order deletes its entries, instead of failing (with
+ * foreign keys enforced) or leaving orphaned entries behind (without).
+ *
+ * SQLite can't change the foreign keys of a table, so order_entry is rebuilt as the SQLite
+ * documentation recommends, with foreign keys off. Orphaned entries are copied as they are; the
+ * server reports them at startup and deletes them if asked to. Foreign keys are turned off per
+ * connection, so the rebuild runs on a single connection of the pool
+ */
+
+const FOREIGN_KEY: &str = "fk_order_entry_order_id";
+
+#[derive(Iden)]
+enum Order {
+    Table,
+    Id,
+}
+
+#[derive(Iden)]
+enum OrderEntry {
+    Table,
+    Id,
+    OrderId,
+    Buyer,
+    Food,
+    Paid,
+    PriceInMillicents,
+}
+
+#[derive(Iden)]
+struct OrderEntryRebuilt;
+
+fn foreign_key(action: ForeignKeyAction) -> ForeignKeyCreateStatement {
+    ForeignKey::create()
+        .name(FOREIGN_KEY)
+        .from(OrderEntry::Table, OrderEntry::OrderId)
+        .to(Order::Table, Order::Id)
+        .on_delete(action)
+        .on_update(action)
+        .to_owned()
+}
+
+fn columns() -> [OrderEntry; 6] {
+    [
+        OrderEntry::Id,
+        OrderEntry::OrderId,
+        OrderEntry::Buyer,
+        OrderEntry::Food,
+        OrderEntry::Paid,
+        OrderEntry::PriceInMillicents,
+    ]
+}
+
+async fn replace_foreign_key(
+    manager: &SchemaManager<'_>,
+    action: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    if manager.get_database_backend() != DatabaseBackend::Sqlite {
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name(FOREIGN_KEY)
+                    .table(OrderEntry::Table)
+                    .to_owned(),
+            )
+            .await?;
+        return manager.create_foreign_key(foreign_key(action)).await;
+    }
+
+    // The migrations run on a pool, and the pragma only affects the connection it runs on
+    let SchemaManagerConnection::Connection(db) = manager.get_connection() else {
+        return Err(DbErr::Custom(
+            "order_entry can't be rebuilt inside a transaction".to_owned(),
+        ));
+    };
+    let mut connection = db
+        .get_sqlite_connection_pool()
+        .acquire()
+        .await
+        .map_err(sqlx_error)?;
+    // Whether the connection enforces foreign keys, which is restored at the end
+    let enforced: i32 = sqlx::query_scalar("PRAGMA foreign_keys")
+        .fetch_one(&mut *connection)
+        .await
+        .map_err(sqlx_error)?;
+    sqlx::query("PRAGMA foreign_keys = OFF")
+        .execute(&mut *connection)
+        .await
+        .map_err(sqlx_error)?;
+    let rebuilt = rebuild_order_entry(&mut connection, action).await;
+    sqlx::query(&format!("PRAGMA foreign_keys = {}", enforced))
+        .execute(&mut *connection)
+        .await
+        .map_err(sqlx_error)?;
+    rebuilt
+}
+
+fn sqlx_error(err: sqlx::Error) -> DbErr {
+    DbErr::Exec(RuntimeErr::SqlxError(err))
+}
+
+/// Copies order_entry into a table with the new foreign key, which replaces it. All of it happens
+/// in one transaction, so a failure leaves order_entry as it was
+async fn rebuild_order_entry(
+    connection: &mut SqliteConnection,
+    action: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    let create = Table::create()
+        .table(OrderEntryRebuilt)
+        .col(
+            ColumnDef::new(OrderEntry::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(OrderEntry::OrderId).integer().not_null())
+        .col(ColumnDef::new(OrderEntry::Buyer).text().not_null())
+        .col(ColumnDef::new(OrderEntry::Food).text().not_null())
+        .col(
+            ColumnDef::new(OrderEntry::Paid)
+                .boolean()
+                .not_null()
+                .default(false),
+        )
+        .col(
+            ColumnDef::new(OrderEntry::PriceInMillicents)
+                .big_unsigned()
+                .not_null()
+                .default(0),
+        )
+        .foreign_key(
+            ForeignKey::create()
+                .from(OrderEntryRebuilt, OrderEntry::OrderId)
+                .to(Order::Table, Order::Id)
+                .on_delete(action)
+                .on_update(action),
+        )
+        .to_string(SqliteQueryBuilder);
+    let copy = Query::insert()
+        .into_table(OrderEntryRebuilt)
+        .columns(columns())
+        .select_from(
+            Query::select()
+                .columns(columns())
+                .from(OrderEntry::Table)
+                .to_owned(),
+        )
+        .map_err(|err| DbErr::Custom(err.to_string()))?
+        .to_string(SqliteQueryBuilder);
+    // Ids of deleted entries aren't handed out again
+    let keep_sequence = "UPDATE sqlite_sequence \
+         SET seq = (SELECT seq FROM sqlite_sequence WHERE name = 'order_entry') \
+         WHERE name = 'order_entry_rebuilt'"
+        .to_owned();
+    let drop = Table::drop()
+        .table(OrderEntry::Table)
+        .to_string(SqliteQueryBuilder);
+    let rename = Table::rename()
+        .table(OrderEntryRebuilt, OrderEntry::Table)
+        .to_string(SqliteQueryBuilder);
+
+    let mut transaction = connection.begin().await.map_err(sqlx_error)?;
+    for statement in [create, copy, keep_sequence, drop, rename] {
+        sqlx::query(&statement)
+            .execute(&mut *transaction)
+            .await
+            .map_err(sqlx_error)?;
+    }
+    transaction.commit().await.map_err(sqlx_error)
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        replace_foreign_key(manager, ForeignKeyAction::Cascade).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        replace_foreign_key(manager, ForeignKeyAction::NoAction).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Migrator, MigratorTrait};
+    use sea_orm_migration::sea_orm::{ConnectionTrait, SqlxSqliteConnector};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    #[async_std::test]
+    async fn rebuilds_order_entry_with_orphans_through_a_pool() {
+        let dir = std::env::temp_dir().join(format!("napoli-cascade-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("napoli.sqlite");
+        let _ = std::fs::remove_file(&file);
+
+        // Connections enforce foreign keys by default, and statements go to whichever is idle
+        let options = SqliteConnectOptions::new()
+            .filename(&file)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .min_connections(4)
+            .max_connections(4)
+            .connect_with(options)
+            .await
+            .unwrap();
+        let db = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool.clone());
+        Migrator::up(&db, Some(11)).await.unwrap();
+
+        let mut connection = pool.acquire().await.unwrap();
+        sqlx::query(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO order_entry (id, order_id, buyer, food, paid, price_in_millicents) \
+             VALUES (7, 42, 'Rob', 'Bufala', false, 1050000);
+             PRAGMA foreign_keys = ON;",
+        )
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+        drop(connection);
+
+        Migrator::up(&db, None).await.unwrap();
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'order_entry%'",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(tables, ["order_entry"]);
+        let orphaned: i64 = sqlx::query_scalar("SELECT count(*) FROM order_entry WHERE id = 7")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(orphaned, 1);
+        let enforced: i32 = sqlx::query_scalar("PRAGMA foreign_keys")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(enforced, 1);
+
+        // Deleting an order now deletes its entries
+        db.execute_unprepared(
+            "INSERT INTO \"order\" (id, menu_url, state) VALUES (1, 'https://napoli.example', 1);
+             INSERT INTO order_entry (id, order_id, buyer, food) VALUES (8, 1, 'Max', 'Don Ciro');
+             DELETE FROM \"order\" WHERE id = 1;",
+        )
+        .await
+        .unwrap();
+        let remaining: Vec<i32> = sqlx::query_scalar("SELECT id FROM order_entry")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, [7]);
+
+        db.close().await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,6 +1,44 @@
+use std::path::Path;
+
 use sea_orm_migration::prelude::*;
 
+const VERIFY_USAGE: &str = "Usage: napoli-server-migrations verify <SQLITE_FILE>";
+
 #[async_std::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let [_, _, file] = &args[..] else {
+            eprintln!("{}", VERIFY_USAGE);
+            std::process::exit(2);
+        };
+        verify(Path::new(file)).await;
+        return;
+    }
     cli::run_cli(napoli_server_migrations::Migrator).await;
 }
+
+/// Prints what each migration loses of the data in `file`, and exits with 1 if any loses some or
+/// can't go up again
+async fn verify(file: &Path) {
+    if !file.is_file() {
+        eprintln!("{} is not a SQLite database file", file.display());
+        std::process::exit(2);
+    }
+    let reports = match napoli_server_migrations::verify::verify(file).await {
+        Ok(reports) => reports,
+        Err(err) => {
+            eprintln!("Could not verify the migrations: {}", err);
+            std::process::exit(1);
+        }
+    };
+    for report in &reports {
+        println!("{}", report);
+    }
+    if reports
+        .iter()
+        .any(|report| !report.losses.is_empty() || report.failure.is_some())
+    {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,457 @@
+//! Checks what the migrations do to the data of a SQLite database before it is upgraded.
+//!
+//! On a copy of the database, every pending migration is applied up and down and every migration
+//! down and up again, and the data before and after each round trip is compared table by table
+//! and column by column. Rows are matched by their rowid, which is the `id` of all tables.
+//!
+//! The migrations are checked as they were shipped, so some can't go up again on data they didn't
+//! expect, e.g. a column without a default that is added back to a table with rows. That is
+//! reported as well, and the older migrations round trip from where it stopped.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{
+    ConnectionTrait, Database, DatabaseConnection, RuntimeErr, SqlxSqliteConnector, Statement,
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+use crate::Migrator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundTrip {
+    UpDown,
+    DownUp,
+}
+
+impl fmt::Display for RoundTrip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoundTrip::UpDown => f.write_str("up and down"),
+            RoundTrip::DownUp => f.write_str("down and up"),
+        }
+    }
+}
+
+/// Data that was there before a round trip and isn't after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Loss {
+    TableDropped {
+        table: String,
+        rows: usize,
+    },
+    RowsDeleted {
+        table: String,
+        rows: usize,
+    },
+    /// `values` counts the values that weren't NULL
+    ColumnDropped {
+        table: String,
+        column: String,
+        values: usize,
+    },
+    ValuesChanged {
+        table: String,
+        column: String,
+        values: usize,
+        /// The first changed value before and after, as SQL literals
+        example: (String, String),
+    },
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Loss::TableDropped { table, rows } => {
+                write!(f, "{}: dropped with {} rows", table, rows)
+            }
+            Loss::RowsDeleted { table, rows } => write!(f, "{}: {} rows deleted", table, rows),
+            Loss::ColumnDropped {
+                table,
+                column,
+                values,
+            } => write!(f, "{}.{}: dropped with {} values", table, column, values),
+            Loss::ValuesChanged {
+                table,
+                column,
+                values,
+                example: (before, after),
+            } => write!(
+                f,
+                "{}.{}: {} values changed, e.g. {} to {}",
+                table,
+                column,
+                values,
+                shortened(before),
+                shortened(after)
+            ),
+        }
+    }
+}
+
+fn shortened(value: &str) -> String {
+    const MAX_CHARS: usize = 40;
+    match value.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}…", &value[..end]),
+        None => value.to_owned(),
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub migration: String,
+    pub round_trip: RoundTrip,
+    pub losses: Vec<Loss>,
+    /// Why the migration couldn't go up again after going down
+    pub failure: Option<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}:", self.migration, self.round_trip)?;
+        if self.losses.is_empty() && self.failure.is_none() {
+            return f.write_str(" no data lost");
+        }
+        for loss in &self.losses {
+            write!(f, "\n  {}", loss)?;
+        }
+        if let Some(failure) = &self.failure {
+            write!(f, "\n  can't go up again: {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct TableData {
+    columns: Vec<String>,
+    /// Values as SQL literals, by rowid
+    rows: BTreeMap<i64, Vec<String>>,
+}
+
+/// All tables but the ones of SQLite and sea-orm
+type Snapshot = BTreeMap<String, TableData>;
+
+async fn snapshot(db: &DatabaseConnection) -> Result<Snapshot, DbErr> {
+    let tables = db
+        .query_all(sqlite(
+            "SELECT name FROM sqlite_master WHERE type = 'table' \
+             AND name NOT LIKE 'sqlite_%' AND name != 'seaql_migrations'",
+        ))
+        .await?;
+
+    let mut snapshot = Snapshot::new();
+    for table in tables {
+        let table: String = table.try_get("", "name")?;
+        let columns = db
+            .query_all(sqlite(&format!("PRAGMA table_info(\"{}\")", table)))
+            .await?
+            .into_iter()
+            .map(|column| column.try_get::<String>("", "name"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let values: Vec<_> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!(", quote(\"{}\") AS value_{}", column, i))
+            .collect();
+        let rows = db
+            .query_all(sqlite(&format!(
+                "SELECT rowid AS row_id{} FROM \"{}\"",
+                values.concat(),
+                table
+            )))
+            .await?
+            .into_iter()
+            .map(|row| {
+                let values = (0..columns.len())
+                    .map(|i| row.try_get::<String>("", &format!("value_{}", i)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((row.try_get("", "row_id")?, values))
+            })
+            .collect::<Result<_, DbErr>>()?;
+
+        snapshot.insert(table, TableData { columns, rows });
+    }
+    Ok(snapshot)
+}
+
+fn sqlite(sql: &str) -> Statement {
+    Statement::from_string(
+        sea_orm_migration::sea_orm::DatabaseBackend::Sqlite,
+        sql.to_owned(),
+    )
+}
+
+/// What is in `before` but not in `after`. New tables, columns, rows and values aren't losses
+fn losses(before: &Snapshot, after: &Snapshot) -> Vec<Loss> {
+    let mut losses = vec![];
+    for (table, old) in before {
+        let new = match after.get(table) {
+            Some(new) => new,
+            None => {
+                if !old.rows.is_empty() {
+                    losses.push(Loss::TableDropped {
+                        table: table.clone(),
+                        rows: old.rows.len(),
+                    });
+                }
+                continue;
+            }
+        };
+
+        let deleted = old
+            .rows
+            .keys()
+            .filter(|row_id| !new.rows.contains_key(row_id))
+            .count();
+        if deleted > 0 {
+            losses.push(Loss::RowsDeleted {
+                table: table.clone(),
+                rows: deleted,
+            });
+        }
+
+        for (i, column) in old.columns.iter().enumerate() {
+            let old_values = old.rows.values().map(|row| &row[i]);
+            let Some(j) = new
+                .columns
+                .iter()
+                .position(|new_column| new_column == column)
+            else {
+                let values = old_values.filter(|value| *value != "NULL").count();
+                if values > 0 {
+                    losses.push(Loss::ColumnDropped {
+                        table: table.clone(),
+                        column: column.clone(),
+                        values,
+                    });
+                }
+                continue;
+            };
+
+            let changed: Vec<_> = old
+                .rows
+                .iter()
+                .filter_map(|(row_id, row)| {
+                    let new_value = &new.rows.get(row_id)?[j];
+                    // Filling in a value that was NULL doesn't lose anything
+                    (row[i] != "NULL" && *new_value != row[i]).then_some((&row[i], new_value))
+                })
+                .collect();
+            if let Some((old_value, new_value)) = changed.first() {
+                losses.push(Loss::ValuesChanged {
+                    table: table.clone(),
+                    column: column.clone(),
+                    values: changed.len(),
+                    example: (old_value.to_string(), new_value.to_string()),
+                });
+            }
+        }
+    }
+    losses
+}
+
+/// Copies the database in `file` with `VACUUM INTO`, which also takes what is still in its
+/// write-ahead log and doesn't need the server to stop
+async fn copy(file: &Path, copy: &Path) -> Result<(), DbErr> {
+    let source = Database::connect(format!("sqlite://{}?mode=ro", file.display())).await?;
+    source
+        .execute(sqlite(&format!(
+            "VACUUM INTO '{}'",
+            copy.display().to_string().replace('\'', "''")
+        )))
+        .await?;
+    source.close().await
+}
+
+/// Round trips all migrations on a copy of the SQLite database in `file`, which stays as it is.
+/// Fails if a migration fails, except for going up again after going down
+pub async fn verify(file: &Path) -> Result<Vec<Report>, DbErr> {
+    let copy_file = std::env::temp_dir().join(format!(
+        "napoli-verify-{}-{}.sqlite",
+        std::process::id(),
+        file.file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default()
+    ));
+    let _ = std::fs::remove_file(&copy_file);
+    copy(file, &copy_file).await?;
+    let reports = round_trip_all(&copy_file).await;
+    let _ = std::fs::remove_file(&copy_file);
+    reports
+}
+
+async fn round_trip_all(file: &Path) -> Result<Vec<Report>, DbErr> {
+    // Without foreign keys, as the first migration drops the orders before their entries. The
+    // round trips run one after the other, on a single connection like sea-orm uses for SQLite
+    let options = SqliteConnectOptions::new()
+        .filename(file)
+        .foreign_keys(false);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .map_err(|err| DbErr::Conn(RuntimeErr::SqlxError(err)))?;
+    let db = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool);
+    let names: Vec<String> = Migrator::migrations()
+        .iter()
+        .map(|migration| migration.name().to_owned())
+        .collect();
+    let applied = Migrator::get_applied_migrations(&db).await?.len();
+
+    let failed = |name: &str, step: &str, err: DbErr| {
+        DbErr::Custom(format!("{} failed to go {}: {}", name, step, err))
+    };
+    let mut reports = vec![];
+
+    // Pending migrations on the data they will find
+    for name in &names[applied..] {
+        let before = snapshot(&db).await?;
+        Migrator::up(&db, Some(1))
+            .await
+            .map_err(|err| failed(name, "up", err))?;
+        Migrator::down(&db, Some(1))
+            .await
+            .map_err(|err| failed(name, "down", err))?;
+        reports.push(Report {
+            migration: name.clone(),
+            round_trip: RoundTrip::UpDown,
+            losses: losses(&before, &snapshot(&db).await?),
+            failure: None,
+        });
+        Migrator::up(&db, Some(1))
+            .await
+            .map_err(|err| failed(name, "up", err))?;
+    }
+
+    // All migrations on the upgraded data, newest first, down to the empty database
+    for name in names.iter().rev() {
+        let before = snapshot(&db).await?;
+        Migrator::down(&db, Some(1))
+            .await
+            .map_err(|err| failed(name, "down", err))?;
+        let failure = Migrator::up(&db, Some(1)).await.err();
+        reports.push(Report {
+            migration: name.clone(),
+            round_trip: RoundTrip::DownUp,
+            losses: losses(&before, &snapshot(&db).await?),
+            failure: failure.as_ref().map(DbErr::to_string),
+        });
+        // A migration that failed to go up is down already
+        if failure.is_none() {
+            Migrator::down(&db, Some(1))
+                .await
+                .map_err(|err| failed(name, "down", err))?;
+        }
+    }
+
+    db.close().await?;
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn reports_lost_values_per_column() {
+        let dir = std::env::temp_dir().join(format!("napoli-verify-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("napoli.sqlite");
+        let _ = std::fs::remove_file(&file);
+
+        // Up to m20230425_2051_price, with a menu url m20250203_200826_throw_away_long_strings
+        // truncates
+        let db = Database::connect(format!("sqlite://{}?mode=rwc", file.display()))
+            .await
+            .unwrap();
+        Migrator::up(&db, Some(5)).await.unwrap();
+        db.execute_unprepared(&format!(
+            "INSERT INTO \"order\" (id, menu_url, state, timestamp) \
+             VALUES (1, '{}', 1, '2024-11-27T10:45:12Z'), (2, 'short', 1, NULL);
+             INSERT INTO order_entry (id, order_id, buyer, food, paid, price_in_millicents) \
+             VALUES (1, 1, 'Rob', 'Bufala', false, 1050000);",
+            "x".repeat(300)
+        ))
+        .await
+        .unwrap();
+        db.close().await.unwrap();
+
+        let reports = verify(&file).await.unwrap();
+        // Verifying doesn't touch the database
+        let db = Database::connect(format!("sqlite://{}", file.display()))
+            .await
+            .unwrap();
+        assert_eq!(
+            Migrator::get_applied_migrations(&db).await.unwrap().len(),
+            5
+        );
+        db.close().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let report = |migration: &str, round_trip: RoundTrip| {
+            reports
+                .iter()
+                .find(|report| report.migration == migration && report.round_trip == round_trip)
+                .unwrap()
+        };
+        let losses =
+            |migration: &str, round_trip: RoundTrip| report(migration, round_trip).losses.clone();
+        assert_eq!(
+            losses(
+                "m20250203_200826_throw_away_long_strings",
+                RoundTrip::UpDown
+            ),
+            [Loss::ValuesChanged {
+                table: "order".to_owned(),
+                column: "menu_url".to_owned(),
+                values: 1,
+                example: (
+                    format!("'{}'", "x".repeat(300)),
+                    format!("'{}'", "x".repeat(210))
+                ),
+            }]
+        );
+        assert!(losses("m20261019_103000_order_created_at", RoundTrip::UpDown).is_empty());
+        assert_eq!(
+            losses("m20241126_202903_add_date_to_order", RoundTrip::DownUp),
+            [Loss::ValuesChanged {
+                table: "order".to_owned(),
+                column: "timestamp".to_owned(),
+                values: 2,
+                example: ("'2024-11-27T10:45:12Z'".to_owned(), "NULL".to_owned()),
+            }]
+        );
+        // The price column was added without a default, which only works on empty tables
+        let price = report("m20230206_005235_order_entry_add_price", RoundTrip::DownUp);
+        assert_eq!(
+            price.losses,
+            [Loss::ColumnDropped {
+                table: "order_entry".to_owned(),
+                column: "price".to_owned(),
+                values: 1,
+            }]
+        );
+        assert!(price
+            .failure
+            .as_ref()
+            .unwrap()
+            .contains("Cannot add a NOT NULL column with default value NULL"));
+        assert_eq!(
+            losses("m20220101_000001_create_table", RoundTrip::DownUp),
+            [
+                Loss::RowsDeleted {
+                    table: "order".to_owned(),
+                    rows: 2
+                },
+                Loss::RowsDeleted {
+                    table: "order_entry".to_owned(),
+                    rows: 1
+                },
+            ]
+        );
+    }
+}
@@ -3,4 +3,6 @@
 pub mod prelude;
 
 pub mod order;
+pub mod order_change;
 pub mod order_entry;
+pub mod sea_orm_active_enums;
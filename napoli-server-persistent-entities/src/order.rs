@@ -1,5 +1,6 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
+use super::sea_orm_active_enums::OrderState;
 use sea_orm::entity::prelude::*;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
@@ -8,8 +9,10 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub menu_url: String,
-    pub state: i32,
-    pub timestamp: Option<String>,
+    pub state: OrderState,
+    pub created_at: TimeDateTimeWithTimeZone,
+    pub version: i32,
+    pub currency: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -22,8 +22,8 @@ pub enum Relation {
         belongs_to = "super::order::Entity",
         from = "Column::OrderId",
         to = "super::order::Column::Id",
-        on_update = "NoAction",
-        on_delete = "NoAction"
+        on_update = "Cascade",
+        on_delete = "Cascade"
     )]
     Order,
 }
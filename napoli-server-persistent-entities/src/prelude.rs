@@ -1,4 +1,5 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
 pub use super::order::Entity as Order;
+pub use super::order_change::Entity as OrderChange;
 pub use super::order_entry::Entity as OrderEntry;
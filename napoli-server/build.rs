@@ -0,0 +1,61 @@
+//! With the embedded-frontend feature, compiles the napoli-pain frontend into the server: every
+//! file in `NAPOLI_FRONTEND_DIST`, `../napoli-pain/dist` by default, which `trunk build --release`
+//! writes. Without the feature, or without a built frontend, nothing is embedded.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=NAPOLI_FRONTEND_DIST");
+
+    let mut files = Vec::new();
+    if env::var_os("CARGO_FEATURE_EMBEDDED_FRONTEND").is_some() {
+        let dist = env::var_os("NAPOLI_FRONTEND_DIST")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                Path::new(&env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("../napoli-pain/dist")
+            });
+        println!("cargo:rerun-if-changed={}", dist.display());
+        if dist.join("index.html").is_file() {
+            collect(&dist, "", &mut files);
+        } else {
+            println!(
+                "cargo:warning=There is no frontend to embed in {}, build it with `trunk build --release` in napoli-pain",
+                dist.display()
+            );
+        }
+    }
+    files.sort();
+
+    let mut table = String::from("&[\n");
+    for (name, path) in files {
+        let path = path.to_str().expect("Frontend paths should be UTF-8");
+        writeln!(table, "    ({:?}, include_bytes!({:?})),", name, path).unwrap();
+    }
+    table.push(']');
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out_dir.join("frontend_assets.rs"), table).unwrap();
+}
+
+/// Adds the files below `dir` as `name/of/the/file` and their absolute path, leaving out hidden
+/// ones like `.htaccess`
+fn collect(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let file_name = entry.file_name();
+        let file_name = file_name.to_str().expect("Frontend paths should be UTF-8");
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let name = format!("{}{}", prefix, file_name);
+        let path = fs::canonicalize(entry.path()).unwrap();
+        if path.is_dir() {
+            collect(&path, &format!("{}/", name), files);
+        } else {
+            files.push((name, path));
+        }
+    }
+}
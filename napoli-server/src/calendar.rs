@@ -0,0 +1,127 @@
+//! When orders were created, and which days that is in the time zones of the people ordering.
+//!
+//! The server stores creation times in UTC and whole seconds, which SQLite can compare as text.
+//! Clients see them as RFC 3339 text in version 1 and as `google.protobuf.Timestamp` in
+//! version 2.
+
+use time::format_description::well_known::Rfc3339;
+use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use time_tz::{Offset, OffsetDateTimeExt, OffsetResult, PrimitiveDateTimeExt, TimeZone, Tz};
+
+/// The creation time of a new order
+pub fn now() -> OffsetDateTime {
+    normalized(OffsetDateTime::now_utc())
+}
+
+/// In UTC and whole seconds, like all stored times
+pub fn normalized(time: OffsetDateTime) -> OffsetDateTime {
+    time.to_offset(UtcOffset::UTC)
+        .replace_nanosecond(0)
+        .expect("0 is a valid nanosecond")
+}
+
+/// The `timestamp` of version 1 orders
+pub fn format(time: OffsetDateTime) -> String {
+    normalized(time)
+        .format(&Rfc3339)
+        .expect("Should be able to format date")
+}
+
+pub fn parse(timestamp: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(timestamp, &Rfc3339)
+        .ok()
+        .map(normalized)
+}
+
+/// An IANA time zone like `Europe/Berlin`. The empty name is UTC
+pub fn time_zone(name: &str) -> Option<&'static Tz> {
+    match name.trim() {
+        "" => Some(time_tz::timezones::db::UTC),
+        name => time_tz::timezones::get_by_name(name),
+    }
+}
+
+/// The day it is in the time zone at `time`
+pub fn day_of(time: OffsetDateTime, time_zone: &Tz) -> Date {
+    time.to_timezone(time_zone).date()
+}
+
+/// A day like `2026-10-19`
+pub fn parse_day(day: &str) -> Option<Date> {
+    Date::parse(
+        day.trim(),
+        time::macros::format_description!("[year]-[month]-[day]"),
+    )
+    .ok()
+}
+
+/// When the day starts in the time zone, and when the next one starts. Days are 23 or 25 hours
+/// long when the clocks change
+pub fn bounds_of_day(day: Date, time_zone: &Tz) -> (OffsetDateTime, OffsetDateTime) {
+    let start = start_of_day(day, time_zone);
+    let end = match day.next_day() {
+        Some(next_day) => start_of_day(next_day, time_zone),
+        None => start + Duration::DAY,
+    };
+    (start, end)
+}
+
+fn start_of_day(day: Date, time_zone: &Tz) -> OffsetDateTime {
+    let midnight = PrimitiveDateTime::new(day, Time::MIDNIGHT);
+    match midnight.assume_timezone(time_zone) {
+        OffsetResult::Some(start) | OffsetResult::Ambiguous(start, _) => normalized(start),
+        // The clocks jumped over midnight, so the day starts when the jump ends: at midnight by
+        // the offset from before the jump
+        OffsetResult::None => {
+            let before = midnight.assume_utc() - Duration::DAY;
+            normalized(midnight.assume_offset(time_zone.get_offset_utc(&before).to_utc()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    #[test]
+    fn days_depend_on_the_time_zone() {
+        let berlin = time_zone("Europe/Berlin").unwrap();
+        let late = datetime!(2026-10-18 22:30 UTC);
+        assert_eq!(day_of(late, berlin), date!(2026 - 10 - 19));
+        assert_eq!(day_of(late, time_zone("").unwrap()), date!(2026 - 10 - 18));
+        assert!(time_zone("Europe/Napoli").is_none());
+
+        assert_eq!(
+            bounds_of_day(date!(2026 - 10 - 19), berlin),
+            (
+                datetime!(2026-10-18 22:00 UTC),
+                datetime!(2026-10-19 22:00 UTC)
+            )
+        );
+        // The clocks go back an hour on the last Sunday of October
+        assert_eq!(
+            bounds_of_day(date!(2026 - 10 - 25), berlin),
+            (
+                datetime!(2026-10-24 22:00 UTC),
+                datetime!(2026-10-25 23:00 UTC)
+            )
+        );
+        // Havana skips midnight when summer time starts
+        let havana = time_zone("America/Havana").unwrap();
+        assert_eq!(
+            bounds_of_day(date!(2026 - 03 - 08), havana).0,
+            datetime!(2026-03-08 05:00 UTC)
+        );
+    }
+
+    #[test]
+    fn stored_times_are_utc_seconds() {
+        let time = parse("2026-10-19T12:30:15.25+02:00").unwrap();
+        assert_eq!(time, datetime!(2026-10-19 10:30:15 UTC));
+        assert_eq!(format(time), "2026-10-19T10:30:15Z");
+        assert_eq!(parse_day("2026-10-19"), Some(date!(2026 - 10 - 19)));
+        assert_eq!(parse_day("19.10.2026"), None);
+        assert!(parse("").is_none());
+    }
+}
@@ -0,0 +1,54 @@
+//! Fan-out of order changes between server instances.
+//!
+//! Every instance keeps its own streams of order updates. When several instances share one
+//! database, they announce changed orders on a [`ChangeBus`], so each of them can refresh the
+//! streams it holds for that order.
+
+use tokio::sync::broadcast;
+
+mod local;
+mod polling;
+#[cfg(feature = "postgres")]
+mod postgres;
+
+pub use local::LocalChangeBus;
+pub use polling::PollingChangeBus;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresChangeBus;
+
+/// How many changes a slow subscriber may fall behind before it misses some
+const CHANNEL_CAPACITY: usize = 256;
+
+#[tonic::async_trait]
+#[allow(clippy::double_must_use)]
+pub trait ChangeBus: Send + Sync {
+    /// Tells all instances, including this one, that the order changed. Failures are only
+    /// logged, the change itself already happened
+    async fn publish(&self, order_id: i32);
+
+    /// Ids of the orders changed by any instance
+    fn subscribe(&self) -> broadcast::Receiver<i32>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use napoli_server_migrations::{Migrator, MigratorTrait};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn polling_reaches_other_instances() {
+        let db = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let interval = Duration::from_millis(10);
+        let first = PollingChangeBus::start(db.clone(), interval).await.unwrap();
+        let second = PollingChangeBus::start(db, interval).await.unwrap();
+        let mut changes = second.subscribe();
+
+        first.publish(3).await;
+        first.publish(1).await;
+        assert_eq!(changes.recv().await.unwrap(), 3);
+        assert_eq!(changes.recv().await.unwrap(), 1);
+    }
+}
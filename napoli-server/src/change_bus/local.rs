@@ -0,0 +1,27 @@
+use tokio::sync::broadcast;
+
+use super::{ChangeBus, CHANNEL_CAPACITY};
+
+/// Only reaches the streams of this process
+pub struct LocalChangeBus {
+    changes: broadcast::Sender<i32>,
+}
+
+impl LocalChangeBus {
+    pub fn new() -> Self {
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        LocalChangeBus { changes }
+    }
+}
+
+#[tonic::async_trait]
+impl ChangeBus for LocalChangeBus {
+    async fn publish(&self, order_id: i32) {
+        // Nobody listening is fine
+        self.changes.send(order_id).ok();
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<i32> {
+        self.changes.subscribe()
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use napoli_server_persistent_entities::order_change;
+use sea_orm::ActiveValue::NotSet;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait};
+use sea_orm::{QueryFilter, QueryOrder, QuerySelect, Set};
+use tokio::sync::broadcast;
+
+use super::{ChangeBus, CHANNEL_CAPACITY};
+
+/// Changes older than this many newer ones are deleted
+const KEEP_CHANGES: i32 = 1000;
+
+/// Appends every change to the `order_change` table and polls it for changes made by other
+/// instances. Works with every database, but is meant for SQLite
+pub struct PollingChangeBus {
+    db_handle: DatabaseConnection,
+    changes: broadcast::Sender<i32>,
+    poller: tokio::task::JoinHandle<()>,
+}
+
+impl PollingChangeBus {
+    /// Only changes published after this call are delivered
+    pub async fn start(db_handle: DatabaseConnection, interval: Duration) -> Result<Self, DbErr> {
+        let last_seq = order_change::Entity::find()
+            .select_only()
+            .column_as(order_change::Column::Seq.max(), "seq")
+            .into_tuple::<Option<i32>>()
+            .one(&db_handle)
+            .await?
+            .flatten()
+            .unwrap_or(0);
+
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let poller = tokio::spawn(poll(db_handle.clone(), last_seq, interval, changes.clone()));
+
+        Ok(PollingChangeBus {
+            db_handle,
+            changes,
+            poller,
+        })
+    }
+}
+
+impl Drop for PollingChangeBus {
+    fn drop(&mut self) {
+        self.poller.abort();
+    }
+}
+
+async fn poll(
+    db_handle: DatabaseConnection,
+    mut last_seq: i32,
+    interval: Duration,
+    changes: broadcast::Sender<i32>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let new_changes = order_change::Entity::find()
+            .filter(order_change::Column::Seq.gt(last_seq))
+            .order_by_asc(order_change::Column::Seq)
+            .all(&db_handle)
+            .await;
+        match new_changes {
+            Ok(new_changes) => {
+                for change in new_changes {
+                    last_seq = change.seq;
+                    changes.send(change.order_id).ok();
+                }
+            }
+            Err(err) => println!("Failed to poll order changes: {}", err),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ChangeBus for PollingChangeBus {
+    async fn publish(&self, order_id: i32) {
+        let change = order_change::ActiveModel {
+            seq: NotSet,
+            order_id: Set(order_id),
+        }
+        .insert(&self.db_handle)
+        .await;
+
+        let result = match change {
+            Ok(change) => order_change::Entity::delete_many()
+                .filter(order_change::Column::Seq.lte(change.seq - KEEP_CHANGES))
+                .exec(&self.db_handle)
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            println!("Failed to publish change of order {}: {}", order_id, err);
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<i32> {
+        self.changes.subscribe()
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, Statement};
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast;
+
+use super::{ChangeBus, CHANNEL_CAPACITY};
+
+const CHANNEL: &str = "napoli_order_changes";
+
+/// Announces changes with Postgres' NOTIFY and receives them with LISTEN
+pub struct PostgresChangeBus {
+    db_handle: DatabaseConnection,
+    changes: broadcast::Sender<i32>,
+    listener: tokio::task::JoinHandle<()>,
+}
+
+impl PostgresChangeBus {
+    /// Listens on its own connection to `database_url`, `db_handle` has to be connected to the
+    /// same database
+    pub async fn start(
+        db_handle: DatabaseConnection,
+        database_url: &str,
+    ) -> Result<Self, sqlx::Error> {
+        let mut listener = PgListener::connect(database_url).await?;
+        listener.listen(CHANNEL).await?;
+
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let listener = tokio::spawn(listen(listener, changes.clone()));
+
+        Ok(PostgresChangeBus {
+            db_handle,
+            changes,
+            listener,
+        })
+    }
+}
+
+impl Drop for PostgresChangeBus {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+async fn listen(mut listener: PgListener, changes: broadcast::Sender<i32>) {
+    loop {
+        match listener.recv().await {
+            Ok(notification) => match notification.payload().parse() {
+                Ok(order_id) => {
+                    changes.send(order_id).ok();
+                }
+                Err(_) => println!("Ignoring order change {:?}", notification.payload()),
+            },
+            // The listener reconnects on the next recv
+            Err(err) => {
+                println!("Lost connection for order changes: {}", err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ChangeBus for PostgresChangeBus {
+    async fn publish(&self, order_id: i32) {
+        let notify = Statement::from_sql_and_values(
+            DatabaseBackend::Postgres,
+            "SELECT pg_notify($1, $2)",
+            [CHANNEL.into(), order_id.to_string().into()],
+        );
+        if let Err(err) = self.db_handle.execute(notify).await {
+            println!("Failed to publish change of order {}: {}", order_id, err);
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<i32> {
+        self.changes.subscribe()
+    }
+}
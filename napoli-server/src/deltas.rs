@@ -0,0 +1,268 @@
+//! Deltas between states of an order for `StreamOrderDeltas`.
+//!
+//! The deltas are computed from the same full states `StreamOrderUpdates` sends. To resume a
+//! stream, the state the client already knows has to be in the [`OrderHistory`], otherwise the
+//! client gets a snapshot.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use futures::{future, Stream, StreamExt};
+use napoli_lib::napoli as npb;
+use npb::order_change::Change;
+
+/// Versions kept per order
+const VERSIONS_PER_ORDER: usize = 16;
+/// Orders kept, the ones changed least recently are dropped first
+const ORDERS: usize = 64;
+/// Clients further behind get a snapshot, even if their version is still known
+const MAX_RESUME_GAP: i32 = 16;
+
+/// The changes that turn `old` into `new`
+pub fn diff(old: &npb::Order, new: &npb::Order) -> Vec<npb::OrderChange> {
+    let old_entries: BTreeMap<i32, &npb::OrderEntry> =
+        old.entries.iter().map(|entry| (entry.id, entry)).collect();
+    let new_entries: BTreeMap<i32, &npb::OrderEntry> =
+        new.entries.iter().map(|entry| (entry.id, entry)).collect();
+
+    let removed = old
+        .entries
+        .iter()
+        .filter(|entry| !new_entries.contains_key(&entry.id))
+        .map(|entry| Change::EntryRemoved(entry.id));
+    let added_or_changed = new
+        .entries
+        .iter()
+        .filter_map(|entry| entry_change(old_entries.get(&entry.id).copied(), entry));
+    let state = (old.state != new.state).then_some(Change::StateChanged(new.state));
+
+    removed
+        .chain(added_or_changed)
+        .chain(state)
+        .map(|change| npb::OrderChange {
+            change: Some(change),
+        })
+        .collect()
+}
+
+fn entry_change(old_entry: Option<&npb::OrderEntry>, entry: &npb::OrderEntry) -> Option<Change> {
+    match old_entry {
+        None => Some(Change::EntryAdded(entry.clone())),
+        Some(old_entry) if old_entry != entry => Some(Change::EntryChanged(entry.clone())),
+        Some(_) => None,
+    }
+}
+
+fn snapshot(order: npb::Order) -> npb::OrderDelta {
+    npb::OrderDelta {
+        order_id: order.id,
+        version: order.version,
+        snapshot: Some(order),
+        changes: vec![],
+    }
+}
+
+/// Recent versions of recently changed orders
+#[derive(Default)]
+pub struct OrderHistory {
+    orders: BTreeMap<i32, RecordedOrder>,
+    changes: u64,
+}
+
+#[derive(Default)]
+struct RecordedOrder {
+    last_change: u64,
+    versions: VecDeque<npb::Order>,
+}
+
+impl OrderHistory {
+    pub fn record(&mut self, order: &npb::Order) {
+        self.changes += 1;
+        let recorded = self.orders.entry(order.id).or_default();
+        recorded.last_change = self.changes;
+        let is_newer = match recorded.versions.back() {
+            Some(last) => last.version < order.version,
+            None => true,
+        };
+        if is_newer {
+            recorded.versions.push_back(order.clone());
+            if recorded.versions.len() > VERSIONS_PER_ORDER {
+                recorded.versions.pop_front();
+            }
+        }
+
+        if self.orders.len() > ORDERS {
+            let least_recent = self
+                .orders
+                .iter()
+                .min_by_key(|(_, recorded)| recorded.last_change)
+                .map(|(order_id, _)| *order_id);
+            if let Some(order_id) = least_recent {
+                self.orders.remove(&order_id);
+            }
+        }
+    }
+
+    /// Version 0 is never known, it is the version of orders from before versions existed
+    pub fn get(&self, order_id: i32, version: i32) -> Option<&npb::Order> {
+        if version == 0 {
+            return None;
+        }
+        self.orders
+            .get(&order_id)?
+            .versions
+            .iter()
+            .find(|order| order.version == version)
+    }
+}
+
+/// Turns the full states of an order into deltas. The first delta is relative to `base`, or a
+/// snapshot without a `base`
+pub fn deltas(
+    updates: impl Stream<Item = tonic::Result<npb::SingleOrderReply>>,
+    base: Option<npb::Order>,
+) -> impl Stream<Item = tonic::Result<npb::OrderDelta>> {
+    updates
+        .scan(base, |last, reply| {
+            let delta = match reply {
+                Ok(npb::SingleOrderReply { order: Some(order) }) => {
+                    let delta = match last.take() {
+                        Some(last)
+                            if last.version <= order.version
+                                && order.version - last.version <= MAX_RESUME_GAP =>
+                        {
+                            let changes = diff(&last, &order);
+                            if changes.is_empty() && last.version == order.version {
+                                None
+                            } else {
+                                Some(npb::OrderDelta {
+                                    order_id: order.id,
+                                    version: order.version,
+                                    snapshot: None,
+                                    changes,
+                                })
+                            }
+                        }
+                        _ => Some(snapshot(order.clone())),
+                    };
+                    *last = Some(order);
+                    delta.map(Ok)
+                }
+                Ok(npb::SingleOrderReply { order: None }) => None,
+                Err(status) => Some(Err(status)),
+            };
+            future::ready(Some(delta))
+        })
+        .filter_map(future::ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i32, paid: bool) -> npb::OrderEntry {
+        npb::OrderEntry {
+            id,
+            food: "Bufala".to_owned(),
+            buyer: "Rob".to_owned(),
+            paid,
+            ..Default::default()
+        }
+    }
+
+    fn order(version: i32, state: npb::OrderState, entries: Vec<npb::OrderEntry>) -> npb::Order {
+        npb::Order {
+            id: 1,
+            state: state as i32,
+            entries,
+            version,
+            ..Default::default()
+        }
+    }
+
+    fn reply(order: &npb::Order) -> tonic::Result<npb::SingleOrderReply> {
+        Ok(npb::SingleOrderReply {
+            order: Some(order.clone()),
+        })
+    }
+
+    #[test]
+    fn diff_finds_all_changes() {
+        let old = order(
+            1,
+            npb::OrderState::Open,
+            vec![entry(1, false), entry(2, false)],
+        );
+        let new = order(
+            4,
+            npb::OrderState::Closed,
+            vec![entry(2, true), entry(3, false)],
+        );
+
+        let changes: Vec<_> = diff(&old, &new)
+            .into_iter()
+            .map(|change| change.change.unwrap())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                Change::EntryRemoved(1),
+                Change::EntryChanged(entry(2, true)),
+                Change::EntryAdded(entry(3, false)),
+                Change::StateChanged(npb::OrderState::Closed as i32),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumes_from_base_or_sends_snapshot() {
+        let v1 = order(1, npb::OrderState::Open, vec![]);
+        let v2 = order(2, npb::OrderState::Open, vec![entry(1, false)]);
+        let v3 = order(3, npb::OrderState::Open, vec![entry(1, true)]);
+
+        let resumed: Vec<_> = deltas(
+            futures::stream::iter([reply(&v2), reply(&v2), reply(&v3)]),
+            Some(v1.clone()),
+        )
+        .map(Result::unwrap)
+        .collect()
+        .await;
+        assert_eq!(resumed.len(), 2);
+        assert_eq!(resumed[0].version, 2);
+        assert!(resumed[0].snapshot.is_none());
+        assert_eq!(resumed[0].changes.len(), 1);
+        assert_eq!(resumed[1].version, 3);
+
+        let fresh: Vec<_> = deltas(futures::stream::iter([reply(&v3)]), None)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(fresh[0].snapshot, Some(v3.clone()));
+
+        let far_ahead = order(MAX_RESUME_GAP + 2, npb::OrderState::Closed, vec![]);
+        let resumed: Vec<_> = deltas(futures::stream::iter([reply(&far_ahead)]), Some(v1))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(resumed[0].snapshot, Some(far_ahead));
+    }
+
+    #[test]
+    fn history_keeps_recent_versions() {
+        let mut history = OrderHistory::default();
+        for version in 1..=VERSIONS_PER_ORDER as i32 + 1 {
+            history.record(&order(version, npb::OrderState::Open, vec![]));
+        }
+        assert!(history.get(1, 1).is_none());
+        assert!(history.get(1, 2).is_some());
+
+        for order_id in 2..=ORDERS as i32 + 1 {
+            history.record(&npb::Order {
+                id: order_id,
+                ..order(1, npb::OrderState::Open, vec![])
+            });
+        }
+        assert!(history.get(1, 2).is_none());
+        assert!(history.get(2, 1).is_some());
+    }
+}
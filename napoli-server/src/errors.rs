@@ -1,8 +1,22 @@
-pub fn map_to_status<T>(err: T) -> tonic::Status
-where
-    T: std::fmt::Display,
-{
-    tonic::Status::internal(err.to_string())
+use napoli_lib::error_details::{ErrorDetails, Reason};
+use tonic::Code;
+
+use crate::repository::RepositoryError;
+
+impl From<RepositoryError> for tonic::Status {
+    fn from(err: RepositoryError) -> Self {
+        let (code, reason) = match err {
+            RepositoryError::OrderNotFound => (Code::NotFound, Reason::OrderNotFound),
+            RepositoryError::OrderEntryNotFound => (Code::NotFound, Reason::OrderEntryNotFound),
+            RepositoryError::OrderNotOpen => (Code::InvalidArgument, Reason::OrderNotOpen),
+            RepositoryError::CurrencyMismatch { .. } => {
+                (Code::FailedPrecondition, Reason::CurrencyMismatch)
+            }
+            RepositoryError::VersionMismatch { .. } => (Code::Aborted, Reason::VersionMismatch),
+            RepositoryError::Backend(_) => (Code::Internal, Reason::StorageError),
+        };
+        ErrorDetails::new(reason).into_status(code, err.to_string())
+    }
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,188 @@
+//! Server-Sent Events for order updates, e.g. `curl -N localhost:50051/events/orders/1`.
+//!
+//! Every change of the order is sent as an `order` event holding the JSON mapping of a
+//! `SingleOrderReply`, fed by the same watch channels as `StreamOrderUpdates`. The stream ends
+//! once the order is done. Failures, like an unknown order, are sent as an `error` event, as
+//! `EventSource` doesn't let the page read the body of failed responses.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::extract::{Path, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use futures::future::BoxFuture;
+use futures::{future, stream, StreamExt};
+use tonic::transport::NamedService;
+use tower::Service;
+
+use crate::rest::{call_router, OrderPath};
+use crate::server::NapoliServer;
+
+/// Serves everything below `/events/` from the same tonic server as the gRPC services
+#[derive(Clone)]
+pub struct EventsGateway {
+    router: axum::Router,
+}
+
+impl EventsGateway {
+    pub fn new(server: Arc<NapoliServer>) -> Self {
+        let router = axum::Router::new()
+            .route("/events/orders/:order_id", get(order_events))
+            .with_state(server);
+
+        EventsGateway { router }
+    }
+}
+
+impl NamedService for EventsGateway {
+    const NAME: &'static str = "events";
+}
+
+impl Service<http::Request<hyper::Body>> for EventsGateway {
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<hyper::Body>) -> Self::Future {
+        call_router(&self.router, req)
+    }
+}
+
+async fn order_events(
+    State(server): State<Arc<NapoliServer>>,
+    Path(OrderPath { order_id }): Path<OrderPath>,
+) -> impl IntoResponse {
+    let updates = match server.subscribe_order_updates(order_id).await {
+        Ok(updates) => updates.boxed(),
+        Err(status) => stream::once(future::ready(Err(status))).boxed(),
+    };
+
+    let events = updates.map(|reply| {
+        let event = match reply {
+            Ok(reply) => Event::default().event("order").json_data(reply),
+            Err(status) => Event::default()
+                .event("error")
+                .json_data(serde_json::json!({
+                    "code": status.code() as i32,
+                    "message": status.message(),
+                })),
+        };
+        Ok::<_, axum::Error>(
+            event.unwrap_or_else(|err| Event::default().event("error").data(err.to_string())),
+        )
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_bus::LocalChangeBus;
+    use crate::repository::MemoryOrderRepository;
+    use crate::streams::StreamLimits;
+    use hyper::body::HttpBody;
+    use napoli_lib::napoli as npb;
+    use napoli_lib::napoli::order_service_server::OrderService;
+    use napoli_lib::validate::Limits;
+    use tower::ServiceExt;
+
+    fn server() -> Arc<NapoliServer> {
+        Arc::new(NapoliServer::new(
+            Arc::new(MemoryOrderRepository::new()),
+            Arc::new(LocalChangeBus::new()),
+            StreamLimits::default(),
+            Limits::default(),
+        ))
+    }
+
+    /// The name and the JSON data of every event in `chunk`
+    fn events(chunk: &[u8]) -> Vec<(String, serde_json::Value)> {
+        std::str::from_utf8(chunk)
+            .unwrap()
+            .split("\n\n")
+            .filter_map(|event| {
+                // The space after the colon is optional
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                        .map(|value| value.strip_prefix(' ').unwrap_or(value).to_owned())
+                };
+                Some((
+                    field("event")?,
+                    serde_json::from_str(&field("data")?).unwrap(),
+                ))
+            })
+            .collect()
+    }
+
+    async fn open(server: &Arc<NapoliServer>, order_id: i32) -> axum::response::Response {
+        let request = http::Request::builder()
+            .uri(format!("/events/orders/{}", order_id))
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = EventsGateway::new(server.clone())
+            .router
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        response
+    }
+
+    #[tokio::test]
+    async fn sends_changes_of_the_order() {
+        let server = server();
+        let order = server
+            .create_order(tonic::Request::new(npb::CreateOrderRequest {
+                menu_url: "https://napoli.example".to_owned(),
+                currency: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        let mut body = open(&server, order.id).await.into_body();
+
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(events(&first)[0].0, "order");
+        assert_eq!(events(&first)[0].1["order"]["id"], order.id);
+
+        server
+            .add_order_entry(tonic::Request::new(npb::AddOrderEntryRequest {
+                order_id: order.id,
+                food: "Bufala".to_owned(),
+                buyer: "Rob".to_owned(),
+                price_in_millicents: 1_050_000,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let update = body.data().await.unwrap().unwrap();
+        let (event, data) = &events(&update)[0];
+        assert_eq!(event, "order");
+        assert_eq!(data["order"]["id"], order.id);
+        assert_eq!(data["order"]["entries"][0]["food"], "Bufala");
+        assert_eq!(data["order"]["entries"][0]["priceInMillicents"], "1050000");
+    }
+
+    #[tokio::test]
+    async fn sends_an_error_for_unknown_orders() {
+        let body = hyper::body::to_bytes(open(&server(), 42).await.into_body())
+            .await
+            .unwrap();
+        let events = events(&body);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, "error");
+        assert_eq!(events[0].1["code"], tonic::Code::NotFound as i32);
+    }
+}
@@ -0,0 +1,399 @@
+//! The napoli-pain web frontend, served next to the APIs so it needs no server of its own.
+//!
+//! GET requests for the files `trunk build` writes are answered with them, and the paths the
+//! frontend routes itself get its `index.html`. `/config.json` tells the frontend where the
+//! backend is, so that doesn't need to be known when building it. Everything else passes on to
+//! the APIs.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use hyper::body::{Bytes, HttpBody};
+use hyper::Body;
+use tonic::body::BoxBody;
+
+use crate::config;
+
+/// The files of the frontend built into the server with the embedded-frontend feature, by their
+/// path in the `dist` directory
+static EMBEDDED: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/frontend_assets.rs"));
+
+#[derive(Debug)]
+pub struct FrontendError(String);
+
+impl fmt::Display for FrontendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FrontendError {}
+
+#[derive(Debug)]
+enum Assets {
+    /// Read on every request, so a new build shows up without a restart
+    Directory(PathBuf),
+    Embedded(&'static [(&'static str, &'static [u8])]),
+}
+
+impl Assets {
+    /// The file at `name`, a path relative to the `dist` directory
+    async fn read(&self, name: &str) -> io::Result<Option<Bytes>> {
+        match self {
+            Assets::Directory(dir) => match tokio::fs::read(dir.join(name)).await {
+                Ok(content) => Ok(Some(content.into())),
+                // Directories are not assets either
+                Err(err) if err.kind() == io::ErrorKind::NotFound || dir.join(name).is_dir() => {
+                    Ok(None)
+                }
+                Err(err) => Err(err),
+            },
+            Assets::Embedded(files) => Ok(files
+                .iter()
+                .find(|(file, _)| *file == name)
+                .map(|(_, content)| Bytes::from_static(content))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Frontend {
+    assets: Assets,
+    config_json: Bytes,
+}
+
+impl Frontend {
+    /// The frontend the configuration asks for, if it asks for one
+    pub fn load(frontend: &config::Frontend) -> Result<Option<Self>, FrontendError> {
+        let assets = match (&frontend.dir, frontend.embedded) {
+            (Some(dir), _) => {
+                if !dir.join("index.html").is_file() {
+                    return Err(FrontendError(format!(
+                        "there is no index.html in {}, build the frontend with `trunk build --release` in napoli-pain",
+                        dir.display()
+                    )));
+                }
+                Assets::Directory(dir.clone())
+            }
+            (None, true) => {
+                if EMBEDDED.is_empty() {
+                    return Err(FrontendError(
+                        "the server was built without a frontend, see the embedded-frontend feature"
+                            .to_owned(),
+                    ));
+                }
+                Assets::Embedded(EMBEDDED)
+            }
+            (None, false) => return Ok(None),
+        };
+        let config_json = serde_json::json!({ "backendUrl": frontend.backend_url });
+        Ok(Some(Frontend {
+            assets,
+            config_json: config_json.to_string().into(),
+        }))
+    }
+
+    /// The response to a GET request for `path`, or nothing if it is not part of the frontend
+    async fn respond(&self, path: &str) -> Option<http::Response<Body>> {
+        if path == "/config.json" {
+            return Some(response("config.json", self.config_json.clone()));
+        }
+
+        let name = asset_name(path)?;
+        let index = is_route(path).then_some("index.html");
+        for name in std::iter::once(name).chain(index) {
+            match self.assets.read(name).await {
+                Ok(Some(content)) => return Some(response(name, content)),
+                Ok(None) => continue,
+                Err(err) => {
+                    eprintln!("Can't read the frontend's {}: {}", name, err);
+                    return Some(
+                        http::Response::builder()
+                            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::empty())
+                            .expect("Should be a valid response"),
+                    );
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The asset a request path asks for, `index.html` for `/`. Paths that would leave the `dist`
+/// directory or name hidden files have none
+fn asset_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix('/')?;
+    if name.is_empty() {
+        return Some("index.html");
+    }
+    let valid = name
+        .split('/')
+        .all(|segment| !segment.is_empty() && !segment.starts_with('.') && !segment.contains('\\'));
+    valid.then_some(name)
+}
+
+/// Whether the frontend's router handles `path`, see `Route` in napoli-pain
+fn is_route(path: &str) -> bool {
+    match path.strip_prefix("/order/") {
+        Some(id) => !id.is_empty() && !id.contains('/'),
+        None => path == "/",
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    match extension {
+        "html" => "text/html; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "wasm" => "application/wasm",
+        "css" => "text/css; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+fn response(name: &str, content: Bytes) -> http::Response<Body> {
+    let builder = http::Response::builder()
+        .header(http::header::CONTENT_TYPE, content_type(name))
+        .header(http::header::CONTENT_LENGTH, content.len());
+    // trunk puts a hash of their content into the names of the other files, but these two keep
+    // their name when they change
+    let builder = if name == "index.html" || name == "config.json" {
+        builder.header(http::header::CACHE_CONTROL, "no-cache")
+    } else {
+        builder
+    };
+    builder
+        .body(Body::from(content))
+        .expect("Should be a valid response")
+}
+
+#[derive(Clone)]
+pub struct FrontendLayer {
+    frontend: Option<Arc<Frontend>>,
+}
+
+impl FrontendLayer {
+    pub fn new(frontend: Option<Frontend>) -> Self {
+        FrontendLayer {
+            frontend: frontend.map(Arc::new),
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for FrontendLayer {
+    type Service = ServeFrontend<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ServeFrontend {
+            inner,
+            frontend: self.frontend.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ServeFrontend<S> {
+    inner: S,
+    frontend: Option<Arc<Frontend>>,
+}
+
+impl<S> tower::Service<http::Request<Body>> for ServeFrontend<S>
+where
+    S: tower::Service<http::Request<Body>, Response = http::Response<BoxBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let head = request.method() == http::Method::HEAD;
+        let frontend = match &self.frontend {
+            Some(frontend) if head || request.method() == http::Method::GET => frontend.clone(),
+            _ => return Box::pin(self.inner.call(request)),
+        };
+        // The ready service has to handle the request, see tower::Service
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let Some(response) = frontend.respond(request.uri().path()).await else {
+                return inner.call(request).await;
+            };
+            Ok(response.map(|body| {
+                let body = if head { Body::empty() } else { body };
+                body.map_err(|err| tonic::Status::internal(err.to_string()))
+                    .boxed_unsync()
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::{Layer, Service, ServiceExt};
+
+    /// The frontend in `frontend`, in front of an API that answers everything with 418
+    fn serve(
+        frontend: Frontend,
+    ) -> impl Service<
+        http::Request<Body>,
+        Response = http::Response<BoxBody>,
+        Error = std::convert::Infallible,
+    > {
+        let api = tower::service_fn(|_: http::Request<Body>| async {
+            Ok(http::Response::builder()
+                .status(http::StatusCode::IM_A_TEAPOT)
+                .body(tonic::body::empty_body())
+                .unwrap())
+        });
+        FrontendLayer::new(Some(frontend)).layer(api)
+    }
+
+    async fn get(
+        service: &mut (impl Service<
+            http::Request<Body>,
+            Response = http::Response<BoxBody>,
+            Error = std::convert::Infallible,
+        > + Send),
+        method: http::Method,
+        path: &str,
+    ) -> (http::StatusCode, Option<String>, String) {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        let response = service.ready().await.unwrap().call(request).await.unwrap();
+        let content_type = response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .map(|content_type| content_type.to_str().unwrap().to_owned());
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8_lossy(&body).into_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn serves_the_frontend_and_passes_on_everything_else() {
+        let dist = tempfile::tempdir().unwrap();
+        std::fs::write(dist.path().join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dist.path().join("napoli-pain-1234_bg.wasm"), "wasm").unwrap();
+        std::fs::write(dist.path().join(".htaccess"), "secret").unwrap();
+        std::fs::create_dir(dist.path().join("order")).unwrap();
+        let frontend = Frontend::load(&config::Frontend {
+            dir: Some(dist.path().to_owned()),
+            embedded: false,
+            backend_url: Some("https://api.napoli.example".to_owned()),
+        })
+        .unwrap()
+        .unwrap();
+        let mut service = serve(frontend);
+
+        let index = (
+            http::StatusCode::OK,
+            Some("text/html; charset=utf-8".to_owned()),
+            "<html></html>".to_owned(),
+        );
+        assert_eq!(get(&mut service, http::Method::GET, "/").await, index);
+        assert_eq!(
+            get(&mut service, http::Method::GET, "/order/7").await,
+            index
+        );
+        assert_eq!(
+            get(&mut service, http::Method::GET, "/napoli-pain-1234_bg.wasm").await,
+            (
+                http::StatusCode::OK,
+                Some("application/wasm".to_owned()),
+                "wasm".to_owned()
+            )
+        );
+        assert_eq!(
+            get(&mut service, http::Method::GET, "/config.json").await,
+            (
+                http::StatusCode::OK,
+                Some("application/json".to_owned()),
+                r#"{"backendUrl":"https://api.napoli.example"}"#.to_owned()
+            )
+        );
+        assert_eq!(
+            get(&mut service, http::Method::HEAD, "/").await,
+            (
+                http::StatusCode::OK,
+                Some("text/html; charset=utf-8".to_owned()),
+                String::new()
+            )
+        );
+
+        for (method, path) in [
+            (http::Method::POST, "/"),
+            (http::Method::GET, "/api/orders"),
+            (http::Method::GET, "/order/7/entries"),
+            (http::Method::GET, "/order"),
+            (http::Method::GET, "/.htaccess"),
+            (http::Method::GET, "/../Cargo.toml"),
+        ] {
+            assert_eq!(
+                get(&mut service, method, path).await.0,
+                http::StatusCode::IM_A_TEAPOT,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_embedded_files() {
+        let frontend = Frontend {
+            assets: Assets::Embedded(&[("index.html", b"<html></html>")]),
+            config_json: r#"{"backendUrl":null}"#.into(),
+        };
+        let mut service = serve(frontend);
+
+        assert_eq!(
+            get(&mut service, http::Method::GET, "/order/7").await.2,
+            "<html></html>"
+        );
+        assert_eq!(
+            get(&mut service, http::Method::GET, "/missing.js").await.0,
+            http::StatusCode::IM_A_TEAPOT
+        );
+        assert!(matches!(
+            Frontend::load(&config::Frontend {
+                dir: Some(PathBuf::from("/nonexistent")),
+                ..config::Frontend::default()
+            }),
+            Err(FrontendError(_))
+        ));
+    }
+}
@@ -0,0 +1,604 @@
+//! Optional GraphQL API over the order model, enabled with the `graphql` feature.
+//!
+//! `/graphql/query` answers queries (POST) and serves GraphiQL (GET), `/graphql/ws` serves
+//! subscriptions, which are fed by the same change notifications as `StreamOrderUpdates`.
+//! Mutations forward to the gRPC handlers, and their errors carry the gRPC `code`, the `reason`
+//! and the `fieldViolations` in their extensions, like the bodies of the REST API.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{
+    Enum, ErrorExtensions, InputObject, Object, Schema, SimpleObject, Subscription,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
+use axum::extract::State;
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt};
+use napoli_lib::error_details::ErrorDetails;
+use napoli_lib::napoli as npb;
+use napoli_lib::napoli::order_service_server::OrderService;
+use napoli_lib::Currency;
+use tonic::transport::NamedService;
+use tower::Service;
+
+use crate::repository::{OrderRepository, RepositoryError};
+use crate::rest::call_router;
+use crate::server::NapoliServer;
+
+pub type NapoliSchema = Schema<Query, Mutation, Subscription>;
+
+/// Serves everything below `/graphql/` from the same tonic server as the gRPC services
+#[derive(Clone)]
+pub struct GraphQLGateway {
+    router: axum::Router,
+}
+
+impl GraphQLGateway {
+    pub fn new(server: Arc<NapoliServer>, repository: Arc<dyn OrderRepository>) -> Self {
+        let schema = schema(server, repository);
+
+        let router = axum::Router::new()
+            .route("/graphql/query", get(graphiql).post(graphql))
+            .route_service("/graphql/ws", GraphQLSubscription::new(schema.clone()))
+            .with_state(schema);
+
+        GraphQLGateway { router }
+    }
+}
+
+fn schema(server: Arc<NapoliServer>, repository: Arc<dyn OrderRepository>) -> NapoliSchema {
+    Schema::build(Query, Mutation, Subscription)
+        .data(repository)
+        .data(server)
+        .finish()
+}
+
+impl NamedService for GraphQLGateway {
+    const NAME: &'static str = "graphql";
+}
+
+impl Service<http::Request<hyper::Body>> for GraphQLGateway {
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<hyper::Body>) -> Self::Future {
+        call_router(&self.router, req)
+    }
+}
+
+async fn graphql(State(schema): State<NapoliSchema>, req: GraphQLRequest) -> GraphQLResponse {
+    schema.execute(req.into_inner()).await.into()
+}
+
+async fn graphiql() -> impl IntoResponse {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/graphql/query")
+            .subscription_endpoint("/graphql/ws")
+            .finish(),
+    )
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// All orders, newest first
+    async fn orders(&self, ctx: &async_graphql::Context<'_>) -> async_graphql::Result<Vec<Order>> {
+        let repository = ctx.data::<Arc<dyn OrderRepository>>()?;
+        let orders = repository.get_orders().await?;
+        Ok(orders.into_iter().map(Order).collect())
+    }
+
+    async fn order(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+    ) -> async_graphql::Result<Option<Order>> {
+        let repository = ctx.data::<Arc<dyn OrderRepository>>()?;
+        match repository.get_order(id).await {
+            Ok(order) => Ok(Some(Order(order))),
+            Err(RepositoryError::OrderNotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The error of a failed gRPC handler, with the details the REST API reports
+fn status_error(status: tonic::Status) -> async_graphql::Error {
+    let details = ErrorDetails::from_status(&status);
+    async_graphql::Error::new(status.message()).extend_with(|_, extensions| {
+        extensions.set("code", status.code() as i32);
+        if let Some(reason) = details.reason {
+            extensions.set("reason", reason.as_str());
+        }
+        if !details.field_violations.is_empty() {
+            let violations: Vec<_> = details
+                .field_violations
+                .iter()
+                .map(|violation| {
+                    let mut fields = async_graphql::indexmap::IndexMap::new();
+                    fields.insert(
+                        async_graphql::Name::new("field"),
+                        violation.field.as_str().into(),
+                    );
+                    fields.insert(
+                        async_graphql::Name::new("description"),
+                        violation.description.as_str().into(),
+                    );
+                    async_graphql::Value::Object(fields)
+                })
+                .collect();
+            extensions.set("fieldViolations", violations);
+        }
+    })
+}
+
+/// The order a gRPC handler replied with
+fn replied_order(
+    reply: tonic::Result<tonic::Response<npb::SingleOrderReply>>,
+) -> async_graphql::Result<Order> {
+    match reply.map_err(status_error)?.into_inner().order {
+        Some(order) => Ok(Order(order)),
+        None => Err("Got empty order".into()),
+    }
+}
+
+#[derive(InputObject)]
+pub struct NewEntry {
+    food: String,
+    buyer: String,
+    price_in_millicents: i64,
+    /// Fails unless it is the currency of the order, if given
+    currency: Option<String>,
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Opens a new order. `currency` is EUR or CHF, EUR if left out
+    async fn create_order(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        menu_url: String,
+        currency: Option<String>,
+    ) -> async_graphql::Result<Order> {
+        let server = ctx.data::<Arc<NapoliServer>>()?;
+        let request = npb::CreateOrderRequest {
+            menu_url,
+            currency: currency.unwrap_or_default(),
+        };
+        replied_order(server.create_order(tonic::Request::new(request)).await)
+    }
+
+    async fn add_entry(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        order_id: i32,
+        entry: NewEntry,
+        expected_version: Option<i32>,
+    ) -> async_graphql::Result<Order> {
+        let server = ctx.data::<Arc<NapoliServer>>()?;
+        let request = npb::AddOrderEntryRequest {
+            order_id,
+            food: entry.food,
+            buyer: entry.buyer,
+            price_in_millicents: entry.price_in_millicents,
+            currency: entry.currency.unwrap_or_default(),
+            expected_version,
+            ..Default::default()
+        };
+        replied_order(server.add_order_entry(tonic::Request::new(request)).await)
+    }
+
+    async fn update_order_state(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        order_id: i32,
+        state: OrderState,
+        expected_version: Option<i32>,
+    ) -> async_graphql::Result<Order> {
+        let server = ctx.data::<Arc<NapoliServer>>()?;
+        let request = npb::UpdateOrderStateRequest {
+            order_id,
+            state: npb::OrderState::from(state) as i32,
+            expected_version,
+        };
+        let reply = server
+            .update_order_state(tonic::Request::new(request))
+            .await;
+        replied_order(reply)
+    }
+
+    async fn remove_entry(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        order_id: i32,
+        entry_id: i32,
+        expected_version: Option<i32>,
+    ) -> async_graphql::Result<Order> {
+        let server = ctx.data::<Arc<NapoliServer>>()?;
+        let request = npb::OrderEntryRequest {
+            order_id,
+            order_entry_id: entry_id,
+            expected_version,
+        };
+        let reply = server
+            .remove_order_entry(tonic::Request::new(request))
+            .await;
+        replied_order(reply)
+    }
+
+    async fn set_entry_paid(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        order_id: i32,
+        entry_id: i32,
+        paid: bool,
+        expected_version: Option<i32>,
+    ) -> async_graphql::Result<Order> {
+        let server = ctx.data::<Arc<NapoliServer>>()?;
+        let request = npb::SetOrderEntryPaidRequest {
+            order_id,
+            order_entry_id: entry_id,
+            paid,
+            expected_version,
+        };
+        let reply = server
+            .set_order_entry_paid(tonic::Request::new(request))
+            .await;
+        replied_order(reply)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Emits the order once and then after every change, until the order is done
+    async fn order_updates(
+        &self,
+        ctx: &async_graphql::Context<'_>,
+        id: i32,
+    ) -> async_graphql::Result<impl Stream<Item = async_graphql::Result<Order>>> {
+        let server = ctx.data::<Arc<NapoliServer>>()?;
+        let updates = server
+            .subscribe_order_updates(id)
+            .await
+            .map_err(status_error)?;
+
+        Ok(updates.map(|reply| match reply {
+            Ok(npb::SingleOrderReply { order: Some(order) }) => Ok(Order(order)),
+            Ok(npb::SingleOrderReply { order: None }) => Err("Got empty order".into()),
+            Err(status) => Err(status_error(status)),
+        }))
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum OrderState {
+    Invalid,
+    Open,
+    Closed,
+    Done,
+}
+
+impl From<i32> for OrderState {
+    fn from(state: i32) -> Self {
+        match npb::OrderState::from_i32(state) {
+            Some(npb::OrderState::Open) => OrderState::Open,
+            Some(npb::OrderState::Closed) => OrderState::Closed,
+            Some(npb::OrderState::Done) => OrderState::Done,
+            Some(npb::OrderState::Invalid) | None => OrderState::Invalid,
+        }
+    }
+}
+
+impl From<OrderState> for npb::OrderState {
+    fn from(state: OrderState) -> Self {
+        match state {
+            OrderState::Invalid => npb::OrderState::Invalid,
+            OrderState::Open => npb::OrderState::Open,
+            OrderState::Closed => npb::OrderState::Closed,
+            OrderState::Done => npb::OrderState::Done,
+        }
+    }
+}
+
+pub struct Order(npb::Order);
+
+#[Object]
+impl Order {
+    async fn id(&self) -> i32 {
+        self.0.id
+    }
+
+    async fn menu_url(&self) -> &str {
+        &self.0.menu_url
+    }
+
+    /// Incremented by every change, see `expectedVersion` of the mutations
+    async fn version(&self) -> i32 {
+        self.0.version
+    }
+
+    async fn state(&self) -> OrderState {
+        self.0.state.into()
+    }
+
+    async fn timestamp(&self) -> &str {
+        &self.0.timestamp
+    }
+
+    /// ISO 4217 code of the currency of all prices of the order
+    async fn currency(&self) -> &str {
+        match self.0.currency.as_str() {
+            "" => Currency::default().code(),
+            currency => currency,
+        }
+    }
+
+    async fn entries(&self) -> Vec<OrderEntry> {
+        self.0.entries.iter().cloned().map(OrderEntry).collect()
+    }
+
+    /// Values in the order or its entries the server would never have accepted. Totals of orders
+    /// with warnings are wrong
+    async fn integrity_warnings(&self) -> &[String] {
+        &self.0.integrity_warnings
+    }
+
+    /// Who owes how much for this order
+    async fn settlement(&self) -> async_graphql::Result<Settlement> {
+        Settlement::from_entries(&self.0.entries).ok_or_else(|| "Total sum overflowed".into())
+    }
+}
+
+pub struct OrderEntry(npb::OrderEntry);
+
+#[Object]
+impl OrderEntry {
+    async fn id(&self) -> i32 {
+        self.0.id
+    }
+
+    async fn food(&self) -> &str {
+        &self.0.food
+    }
+
+    async fn buyer(&self) -> &str {
+        &self.0.buyer
+    }
+
+    async fn price_in_millicents(&self) -> i64 {
+        self.0.price_in_millicents
+    }
+
+    async fn paid(&self) -> bool {
+        self.0.paid
+    }
+
+    async fn integrity_warnings(&self) -> &[String] {
+        &self.0.integrity_warnings
+    }
+}
+
+#[derive(SimpleObject, Default)]
+pub struct Settlement {
+    total_in_millicents: i64,
+    paid_in_millicents: i64,
+    unpaid_in_millicents: i64,
+    buyers: Vec<BuyerSettlement>,
+}
+
+#[derive(SimpleObject)]
+pub struct BuyerSettlement {
+    buyer: String,
+    total_in_millicents: i64,
+    paid_in_millicents: i64,
+    unpaid_in_millicents: i64,
+}
+
+impl Settlement {
+    /// Sums up the entries per buyer, `None` if any sum overflows
+    fn from_entries(entries: &[npb::OrderEntry]) -> Option<Self> {
+        let mut per_buyer: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+        for entry in entries {
+            let (paid, unpaid) = per_buyer.entry(entry.buyer.trim()).or_default();
+            if entry.paid {
+                *paid = paid.checked_add(entry.price_in_millicents)?;
+            } else {
+                *unpaid = unpaid.checked_add(entry.price_in_millicents)?;
+            }
+        }
+
+        let mut settlement = Settlement::default();
+        for (buyer, (paid, unpaid)) in per_buyer {
+            let total = paid.checked_add(unpaid)?;
+            settlement.total_in_millicents = settlement.total_in_millicents.checked_add(total)?;
+            settlement.paid_in_millicents = settlement.paid_in_millicents.checked_add(paid)?;
+            settlement.unpaid_in_millicents =
+                settlement.unpaid_in_millicents.checked_add(unpaid)?;
+            settlement.buyers.push(BuyerSettlement {
+                buyer: buyer.to_owned(),
+                total_in_millicents: total,
+                paid_in_millicents: paid,
+                unpaid_in_millicents: unpaid,
+            });
+        }
+        Some(settlement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_bus::LocalChangeBus;
+    use crate::repository::MemoryOrderRepository;
+    use crate::streams::StreamLimits;
+    use napoli_lib::validate::Limits;
+
+    fn test_schema() -> NapoliSchema {
+        let repository: Arc<dyn OrderRepository> = Arc::new(MemoryOrderRepository::new());
+        let server = Arc::new(NapoliServer::new(
+            repository.clone(),
+            Arc::new(LocalChangeBus::new()),
+            StreamLimits::default(),
+            Limits::default(),
+        ));
+        schema(server, repository)
+    }
+
+    fn data(response: async_graphql::Response) -> serde_json::Value {
+        assert_eq!(response.errors, [], "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    #[tokio::test]
+    async fn queries_orders_with_their_settlement() {
+        let schema = test_schema();
+        let created = data(
+            schema
+                .execute(r#"mutation { createOrder(menuUrl: "https://napoli.example") { id } }"#)
+                .await,
+        );
+        let order_id = created["createOrder"]["id"].as_i64().unwrap();
+        for (buyer, price) in [("Rob", 1_050_000), ("Max", 1_250_000), ("Rob", 200_000)] {
+            let mutation = format!(
+                r#"mutation {{ addEntry(orderId: {}, entry: {{ food: "Pizza", buyer: "{}", priceInMillicents: {} }}) {{ id }} }}"#,
+                order_id, buyer, price
+            );
+            data(schema.execute(&mutation).await);
+        }
+
+        let orders = data(
+            schema
+                .execute(
+                    "{ orders { id version currency entries { buyer } settlement { \
+                 totalInMillicents buyers { buyer totalInMillicents } } } }",
+                )
+                .await,
+        );
+        assert_eq!(
+            orders,
+            serde_json::json!({ "orders": [{
+                "id": order_id,
+                "version": 4,
+                "currency": "EUR",
+                "entries": [{ "buyer": "Rob" }, { "buyer": "Max" }, { "buyer": "Rob" }],
+                "settlement": {
+                    "totalInMillicents": 2_500_000,
+                    "buyers": [
+                        { "buyer": "Max", "totalInMillicents": 1_250_000 },
+                        { "buyer": "Rob", "totalInMillicents": 1_250_000 },
+                    ],
+                },
+            }] })
+        );
+    }
+
+    #[tokio::test]
+    async fn mutations_report_the_reason_of_errors() {
+        let schema = test_schema();
+        let response = schema
+            .execute(
+                r#"mutation {
+                    addEntry(
+                        orderId: 42
+                        entry: { food: "Bufala", buyer: "Rob", priceInMillicents: 1050000 }
+                    ) {
+                        id
+                    }
+                }"#,
+            )
+            .await;
+        let error = &response.errors[0];
+        let extensions = error.extensions.as_ref().unwrap();
+        assert_eq!(
+            extensions.get("code").cloned(),
+            Some((tonic::Code::NotFound as i32).into())
+        );
+        assert_eq!(
+            extensions.get("reason").cloned(),
+            Some("ORDER_NOT_FOUND".into())
+        );
+
+        let mutation = format!(
+            r#"mutation {{ createOrder(menuUrl: "{}") {{ id }} }}"#,
+            "x".repeat(300)
+        );
+        let response = schema.execute(&mutation).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(
+            extensions.get("code").cloned(),
+            Some((tonic::Code::InvalidArgument as i32).into())
+        );
+        assert_eq!(
+            extensions.get("reason").cloned(),
+            Some("FIELD_TOO_LONG".into())
+        );
+        let violations = extensions.get("fieldViolations").unwrap().clone();
+        assert_eq!(violations.into_json().unwrap()[0]["field"], "menu_url");
+    }
+
+    #[tokio::test]
+    async fn subscriptions_follow_the_order() {
+        let schema = test_schema();
+        let created = data(
+            schema
+                .execute(r#"mutation { createOrder(menuUrl: "https://napoli.example") { id } }"#)
+                .await,
+        );
+        let order_id = created["createOrder"]["id"].as_i64().unwrap();
+
+        let subscription = format!(
+            "subscription {{ orderUpdates(id: {}) {{ version state }} }}",
+            order_id
+        );
+        let mut updates = Box::pin(schema.execute_stream(subscription.as_str()));
+        let first = data(updates.next().await.unwrap());
+        assert_eq!(
+            first,
+            serde_json::json!({ "orderUpdates": { "version": 1, "state": "OPEN" } })
+        );
+
+        let mutation = format!(
+            "mutation {{ updateOrderState(orderId: {}, state: DONE) {{ id }} }}",
+            order_id
+        );
+        data(schema.execute(&mutation).await);
+        let done = data(updates.next().await.unwrap());
+        assert_eq!(
+            done,
+            serde_json::json!({ "orderUpdates": { "version": 2, "state": "DONE" } })
+        );
+        assert!(updates.next().await.is_none());
+
+        let unknown = schema
+            .execute_stream("subscription { orderUpdates(id: 42) { id } }")
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(
+            unknown.errors[0]
+                .extensions
+                .as_ref()
+                .unwrap()
+                .get("reason")
+                .cloned(),
+            Some("ORDER_NOT_FOUND".into())
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Checks of stored orders for values the server would never have accepted, e.g. prices written
+//! by old versions or by hand. Instead of hiding them, the server reports them in the
+//! `integrity_warnings` of the order and the entry, so clients know that totals are wrong.
+
+use napoli_lib::napoli as npb;
+use napoli_lib::Currency;
+
+use crate::repository::{OrderRepository, Result};
+
+/// What is wrong with the entry, empty if nothing is
+pub fn entry_warnings(entry: &npb::OrderEntry) -> Vec<String> {
+    let mut warnings = vec![];
+    if entry.price_in_millicents < 0 {
+        warnings.push(format!(
+            "price_in_millicents {} is negative",
+            entry.price_in_millicents
+        ));
+    }
+    warnings
+}
+
+/// The warning for an order stored in `state`, which isn't one
+pub fn state_warning(state: i32) -> String {
+    format!("state {} is not a valid order state", state)
+}
+
+/// Replaces the warnings of the order and its entries by what is wrong with them now. The
+/// warnings of the order include those of its entries
+pub fn mark(order: &mut npb::Order) {
+    let mut warnings = vec![];
+    match npb::OrderState::from_i32(order.state) {
+        Some(npb::OrderState::Open | npb::OrderState::Closed | npb::OrderState::Done) => {}
+        Some(npb::OrderState::Invalid) | None => warnings.push(state_warning(order.state)),
+    }
+    if Currency::from_code(&order.currency).is_none() {
+        warnings.push(format!("currency {:?} is unknown", order.currency));
+    }
+    for entry in &mut order.entries {
+        entry.integrity_warnings = entry_warnings(entry);
+        warnings.extend(
+            entry
+                .integrity_warnings
+                .iter()
+                .map(|warning| format!("entry {}: {}", entry.id, warning)),
+        );
+    }
+    order.integrity_warnings = warnings;
+}
+
+/// All orders with integrity warnings, newest first
+pub async fn tainted_orders(repository: &dyn OrderRepository) -> Result<Vec<npb::Order>> {
+    let orders = repository.get_orders().await?;
+    Ok(orders
+        .into_iter()
+        .filter(|order| order.is_tainted())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warnings_name_the_broken_values() {
+        let mut order = napoli_lib::create_example_order();
+        mark(&mut order);
+        assert!(!order.is_tainted());
+
+        order.entries[1].price_in_millicents = -5;
+        order.currency = "XYZ".to_owned();
+        mark(&mut order);
+        assert!(!order.entries[0].is_tainted());
+        assert_eq!(
+            order.entries[1].integrity_warnings,
+            ["price_in_millicents -5 is negative"]
+        );
+        assert_eq!(
+            order.integrity_warnings,
+            [
+                "currency \"XYZ\" is unknown",
+                "entry 2: price_in_millicents -5 is negative"
+            ]
+        );
+
+        // Fixing the data clears the warnings
+        order.entries.pop();
+        order.currency = "EUR".to_owned();
+        mark(&mut order);
+        assert!(!order.is_tainted());
+    }
+}
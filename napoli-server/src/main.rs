@@ -1,70 +1,380 @@
+// tonic::Status is large, but it is what every handler returns
+#![allow(clippy::result_large_err)]
+
+mod calendar;
+mod change_bus;
+mod config;
+mod deltas;
 mod errors;
+mod events;
+mod frontend;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod integrity;
 mod model_adapters;
+mod repository;
+mod rest;
 mod server;
+mod streams;
+mod tls;
+mod v2;
 mod validate;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use napoli_lib::napoli::order_service_server::OrderServiceServer;
+use napoli_lib::napoli::v2::order_service_server::OrderServiceServer as OrderServiceServerV2;
 use napoli_lib::napoli::FILE_DESCRIPTOR_SET;
 use napoli_server_migrations::{Migrator, MigratorTrait};
+use sea_orm::DatabaseConnection;
+#[cfg(feature = "postgres")]
+use sea_orm::{ConnectionTrait, DatabaseBackend};
 use tonic_web::GrpcWebLayer;
+use tower::Layer;
 use tower_http::cors;
 
+use crate::change_bus::{ChangeBus, LocalChangeBus, PollingChangeBus};
+use crate::config::Config;
+use crate::events::EventsGateway;
+use crate::frontend::{Frontend, FrontendLayer};
+use crate::repository::{
+    DatabaseOrderRepository, KeyValueOrderRepository, MemoryOrderRepository, OrderRepository,
+};
+use crate::rest::RestGateway;
 use crate::server::NapoliServer;
+use crate::streams::StreamLimits;
+use crate::tls::{AdminOnlyLayer, Termination};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Default, Debug)]
 struct Arguments {
-    #[clap(short, long, default_value = "[::1]:50051")]
-    bind_addr: String,
+    /// TOML configuration file, whose keys environment variables like NAPOLI_BIND_ADDR override
+    #[clap(long)]
+    config: Option<PathBuf>,
+    /// Print the configuration the server would run with, without the database password, and
+    /// check it, instead of serving
+    #[clap(long)]
+    check_config: bool,
+    /// Overrides `bind_addr` of the configuration, [::1]:50051 by default
+    #[clap(short, long)]
+    bind_addr: Option<SocketAddr>,
     #[clap(short, long, default_value = "napoli.sqlite")]
     sqlite_file_name: String,
+    /// e.g. postgres://napoli@localhost/napoli, replaces `sqlite_file_name`. Overrides
+    /// `database_url` of the configuration
+    #[clap(long)]
+    database_url: Option<String>,
+    #[clap(short, long, default_value = "napoli.kv")]
+    key_value_path: String,
+    #[clap(long, value_enum, default_value_t)]
+    storage: Storage,
+    /// How changes reach the streams of other instances sharing the database
+    #[clap(long, value_enum, default_value_t)]
+    change_bus: Bus,
+    #[clap(long, default_value_t = 1000)]
+    poll_interval_ms: u64,
+    /// Open streams of order updates per order
+    #[clap(long, default_value_t = StreamLimits::default().per_order)]
+    max_streams_per_order: usize,
+    /// Open streams of order updates in total
+    #[clap(long, default_value_t = StreamLimits::default().total)]
+    max_streams: usize,
+    /// Delete order entries whose order doesn't exist, which the database reports at startup
+    #[clap(long)]
+    delete_orphaned_entries: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug)]
+enum Storage {
+    /// The database at `database_url`, by default the SQLite database in `sqlite_file_name`
+    #[default]
+    #[value(alias = "sqlite")]
+    Database,
+    /// The embedded key-value store in the `key_value_path` directory
+    KeyValue,
+    /// Keep all orders in memory, they are lost when the server stops
+    Memory,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug)]
+enum Bus {
+    /// Only streams of this instance get updated
+    #[default]
+    Local,
+    /// Postgres' LISTEN/NOTIFY, needs the postgres feature
+    Postgres,
+    /// Poll a change table in the database every `poll_interval_ms`
+    Polling,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Copy all orders from one storage into another, empty one instead of serving
+    MigrateStorage {
+        #[clap(long, value_enum)]
+        from: Storage,
+        #[clap(long, value_enum)]
+        to: Storage,
+    },
+    /// List the orders in `storage` with values the server would never have accepted, e.g.
+    /// negative prices, instead of serving. Fails if there are any
+    CheckIntegrity,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Arguments::parse();
+    let config = load_config(&args)?;
 
-    assert_db_file_exists(&args.sqlite_file_name)?;
-    let conn = format!("sqlite://{}", &args.sqlite_file_name);
-    let db = sea_orm::Database::connect(conn).await?;
+    if args.check_config {
+        print!("{}", toml::to_string(&config.redacted())?);
+        config.check()?;
+        Termination::load(&config.tls)?;
+        Frontend::load(&config.frontend)?;
+        println!("# The configuration is valid");
+        return Ok(());
+    }
+    config.check()?;
 
-    Migrator::up(&db, None).await?;
+    if let Some(Command::MigrateStorage { from, to }) = args.command {
+        let (source, _) = open_repository(from, &args, &config).await?;
+        let (target, _) = open_repository(to, &args, &config).await?;
+        let count = repository::copy_orders(source.as_ref(), target.as_ref()).await?;
+        println!("Copied {} orders from {:?} to {:?}", count, from, to);
+        return Ok(());
+    }
+
+    if let Some(Command::CheckIntegrity) = args.command {
+        let (repository, _) = open_repository(args.storage, &args, &config).await?;
+        return check_integrity(repository.as_ref()).await;
+    }
+
+    let (repository, database) = open_repository(args.storage, &args, &config).await?;
 
-    let addr = match args.bind_addr.parse() {
-        Ok(addr) => addr,
-        Err(e) => {
-            println!("Failed to parse address: {}", e);
-            return Ok(());
+    let termination = Termination::load(&config.tls)?;
+    let frontend = Frontend::load(&config.frontend)?;
+    let addr = config.bind_addr;
+    println!(
+        "NapoliServer listening on {} ({})",
+        addr,
+        if termination.is_some() {
+            "HTTPS"
+        } else {
+            "plain HTTP"
         }
+    );
+    if frontend.is_some() {
+        println!("Serving the napoli-pain frontend at /");
+    }
+    let change_bus = open_change_bus(&args, &config, database).await?;
+    let stream_limits = StreamLimits {
+        per_order: args.max_streams_per_order,
+        total: args.max_streams,
     };
+    let napoli_server = Arc::new(NapoliServer::new(
+        repository.clone(),
+        change_bus,
+        stream_limits,
+        config.validation_limits()?,
+    ));
 
-    println!("NapoliServer listening on {}", addr);
-    let napoli_server = NapoliServer::with_connection(db);
-
-    let order_service_server = OrderServiceServer::new(napoli_server);
-    let reflection = tonic_reflection::server::Builder::configure()
-        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
-        .build()
-        .unwrap();
+    let order_service_server = OrderServiceServer::from_arc(napoli_server.clone());
+    let order_service_server_v2 =
+        OrderServiceServerV2::new(v2::NapoliServerV2::new(napoli_server.clone()));
+    let features = &config.features;
+    let reflection = features.reflection.then(|| {
+        tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build()
+            .unwrap()
+    });
+    let rest_gateway = features
+        .rest
+        .then(|| RestGateway::new(napoli_server.clone()));
+    let events_gateway = features
+        .events
+        .then(|| EventsGateway::new(napoli_server.clone()));
 
     let cors = cors::CorsLayer::new()
         .allow_headers(cors::Any)
-        .allow_methods([http::Method::POST])
-        .allow_origin(cors::Any);
+        .allow_methods([
+            http::Method::GET,
+            http::Method::POST,
+            http::Method::PUT,
+            http::Method::DELETE,
+        ])
+        .allow_origin(config.allow_origin()?);
 
-    tonic::transport::Server::builder()
+    // grpc-web is enabled per service, as the layer would reject the plain HTTP/1 requests of
+    // the REST and SSE gateways
+    let router = tonic::transport::Server::builder()
         .accept_http1(true)
         .layer(cors)
-        .layer(GrpcWebLayer::new())
-        .add_service(order_service_server)
-        .add_service(reflection)
-        .serve(addr)
-        .await?;
+        .layer(AdminOnlyLayer::new(config.tls.admin_client_ca.is_some()))
+        .layer(FrontendLayer::new(frontend))
+        .add_service(GrpcWebLayer::new().layer(order_service_server))
+        .add_service(GrpcWebLayer::new().layer(order_service_server_v2))
+        .add_optional_service(reflection.map(|reflection| GrpcWebLayer::new().layer(reflection)))
+        .add_optional_service(rest_gateway)
+        .add_optional_service(events_gateway);
+
+    #[cfg(feature = "graphql")]
+    let router = router.add_optional_service(
+        features
+            .graphql
+            .then(|| graphql::GraphQLGateway::new(napoli_server, repository)),
+    );
+
+    match termination {
+        Some(termination) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            router
+                .serve_with_incoming(termination.incoming(listener))
+                .await?
+        }
+        None => router.serve(addr).await?,
+    }
 
     Ok(())
 }
 
+/// The configuration file with the environment and the command line applied
+fn load_config(args: &Arguments) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config = Config::load(args.config.as_deref(), |name| std::env::var(name).ok())?;
+    if let Some(bind_addr) = args.bind_addr {
+        config.bind_addr = bind_addr;
+    }
+    if let Some(database_url) = &args.database_url {
+        config.database_url = Some(database_url.clone());
+    }
+    Ok(config)
+}
+
+async fn check_integrity(
+    repository: &dyn OrderRepository,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tainted = integrity::tainted_orders(repository).await?;
+    for order in &tainted {
+        println!("Order {}:", order.id);
+        for warning in &order.integrity_warnings {
+            println!("  {}", warning);
+        }
+    }
+
+    if tainted.is_empty() {
+        println!("No integrity warnings");
+        Ok(())
+    } else {
+        Err(format!("{} orders have integrity warnings", tainted.len()).into())
+    }
+}
+
+/// The repository of `storage`, and its database connection if it has one, which the change bus
+/// shares
+async fn open_repository(
+    storage: Storage,
+    args: &Arguments,
+    config: &Config,
+) -> Result<(Arc<dyn OrderRepository>, Option<DatabaseConnection>), Box<dyn std::error::Error>> {
+    Ok(match storage {
+        Storage::Database => {
+            let db = connect_database(args, config).await?;
+            let repository = DatabaseOrderRepository::new(db.clone());
+            check_orphaned_entries(&repository, args.delete_orphaned_entries).await?;
+            (Arc::new(repository), Some(db))
+        }
+        Storage::KeyValue => (
+            Arc::new(KeyValueOrderRepository::open(&args.key_value_path)?),
+            None,
+        ),
+        Storage::Memory => (Arc::new(MemoryOrderRepository::new()), None),
+    })
+}
+
+/// Reports entries whose order doesn't exist, or deletes them with `delete`
+async fn check_orphaned_entries(
+    repository: &DatabaseOrderRepository,
+    delete: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if delete {
+        let deleted = repository.delete_orphaned_entries().await?;
+        if deleted > 0 {
+            println!(
+                "Deleted {} order entries whose order doesn't exist",
+                deleted
+            );
+        }
+        return Ok(());
+    }
+
+    let orphaned = repository.orphaned_entries().await?;
+    if !orphaned.is_empty() {
+        println!(
+            "{} order entries belong to orders that don't exist: {:?}. \
+             Start with --delete-orphaned-entries to delete them",
+            orphaned.len(),
+            orphaned
+        );
+    }
+    Ok(())
+}
+
+async fn connect_database(
+    args: &Arguments,
+    config: &Config,
+) -> Result<sea_orm::DatabaseConnection, Box<dyn std::error::Error>> {
+    let conn = match &config.database_url {
+        Some(database_url) => database_url.clone(),
+        None => {
+            assert_db_file_exists(&args.sqlite_file_name)?;
+            format!("sqlite://{}", args.sqlite_file_name)
+        }
+    };
+    let db = repository::connect(&conn).await?;
+
+    Migrator::up(&db, None).await?;
+    Ok(db)
+}
+
+/// The buses other than the local one use `database`, the connection of the repository
+async fn open_change_bus(
+    args: &Arguments,
+    // Only the postgres bus needs the database URL
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))] config: &Config,
+    database: Option<DatabaseConnection>,
+) -> Result<Arc<dyn ChangeBus>, Box<dyn std::error::Error>> {
+    let shared_database = move || {
+        database.ok_or_else(|| -> Box<dyn std::error::Error> {
+            "Only --storage database can be shared with other instances".into()
+        })
+    };
+
+    Ok(match args.change_bus {
+        Bus::Local => Arc::new(LocalChangeBus::new()),
+        Bus::Polling => {
+            let interval = std::time::Duration::from_millis(args.poll_interval_ms);
+            Arc::new(PollingChangeBus::start(shared_database()?, interval).await?)
+        }
+        #[cfg(feature = "postgres")]
+        Bus::Postgres => {
+            let db = shared_database()?;
+            match &config.database_url {
+                Some(database_url) if db.get_database_backend() == DatabaseBackend::Postgres => {
+                    Arc::new(change_bus::PostgresChangeBus::start(db, database_url).await?)
+                }
+                _ => return Err("--change-bus postgres needs a postgres:// --database-url".into()),
+            }
+        }
+        #[cfg(not(feature = "postgres"))]
+        Bus::Postgres => return Err("Built without the postgres feature".into()),
+    })
+}
+
 fn assert_db_file_exists(file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
     if std::path::Path::new(file_name).exists() {
         println!("Database file already exists; skipping creating");
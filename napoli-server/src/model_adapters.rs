@@ -1,53 +1,148 @@
 use napoli_lib::{
-    napoli::{AddOrderEntryRequest, CreateOrderRequest, SingleOrderReply},
-    Millicents,
+    napoli::{self as npb, AddOrderEntryRequest, CreateOrderRequest},
+    validate::{self as validate_lib, Buyer, Food, Limits},
+    Currency, Millicents,
 };
-use sea_orm::{ActiveValue::NotSet, Set};
-use time::format_description::well_known::Rfc3339;
+use napoli_server_persistent_entities::sea_orm_active_enums::OrderState;
+
+use crate::calendar;
+use crate::integrity;
+use crate::repository::{NewOrder, NewOrderEntry};
+use crate::validate;
 
 pub fn get_order_from_create_request(
     request: CreateOrderRequest,
-) -> Option<napoli_server_persistent_entities::order::ActiveModel> {
-    let ts_str: String = time::OffsetDateTime::now_utc()
-        .format(&Rfc3339)
-        .expect("Should be able to format date");
+    limits: &Limits,
+) -> Result<NewOrder, tonic::Status> {
+    new_order(limits, &request.menu_url, "currency", &request.currency)
+}
+
+/// Checks the fields of a new order all versions of the protocol share. `currency_field` is the
+/// name of the currency in the request
+pub fn new_order(
+    limits: &Limits,
+    menu_url: &str,
+    currency_field: &'static str,
+    currency: &str,
+) -> Result<NewOrder, tonic::Status> {
+    let menu_url = validate::field("menu_url", limits.menu_url(menu_url))?;
+    let currency = validate::field(currency_field, validate_lib::currency(currency))?;
 
-    Some(napoli_server_persistent_entities::order::ActiveModel {
-        id: NotSet,
-        menu_url: Set(request.menu_url),
-        // You can replace with: #[sea_orm(default_value="1")] in the model definition,
-        // but loose the ability to use the enum directly there, this is why we do it here
-        state: Set(napoli_lib::napoli::OrderState::Open as i32),
-        timestamp: Set(Some(ts_str)),
+    Ok(NewOrder {
+        menu_url: menu_url.into_inner(),
+        created_at: calendar::now(),
+        currency,
     })
 }
 
 pub fn get_order_entry_from_add_request(
     request: AddOrderEntryRequest,
-) -> Option<napoli_server_persistent_entities::order_entry::ActiveModel> {
+    limits: &Limits,
+) -> Result<NewOrderEntry, tonic::Status> {
+    let (food, buyer, currency) = entry_fields(
+        limits,
+        &request.food,
+        &request.buyer,
+        "currency",
+        &request.currency,
+    )?;
     // This is to support the migration from price to price_in_millicents for the protocol
-    let price_in_millicents = match if request.price_deprecated > 0.0 {
-        napoli_lib::Millicents::from_euro_float(request.price_deprecated)
+    let price = if request.price_deprecated > 0.0 {
+        validate::field(
+            "price_deprecated",
+            limits.price_from_euro_float(request.price_deprecated),
+        )?
     } else {
-        napoli_lib::Millicents::from_raw(request.price_in_millicents)
-    } {
-        Ok(v) => v,
-        Err(e) => {
-            println!("Failed to parse: {:?}", e);
-            return None;
-        }
+        validate::field(
+            "price_in_millicents",
+            limits.price_from_millicents(request.price_in_millicents),
+        )?
+    };
+
+    Ok(NewOrderEntry {
+        order_id: request.order_id,
+        buyer: buyer.into_inner(),
+        food: food.into_inner(),
+        price_in_millicents: price.millicents(),
+        currency,
+    })
+}
+
+/// Checks the fields of a new entry all versions of the protocol share; each version has its own
+/// price. An empty currency is the one of the order
+pub fn entry_fields(
+    limits: &Limits,
+    food: &str,
+    buyer: &str,
+    currency_field: &'static str,
+    currency: &str,
+) -> Result<(Food, Buyer, Option<Currency>), tonic::Status> {
+    let food = validate::field("food", limits.food(food))?;
+    let buyer = validate::field("buyer", limits.buyer(buyer))?;
+    let currency = match currency.trim() {
+        "" => None,
+        code => Some(validate::field(
+            currency_field,
+            validate_lib::currency(code),
+        )?),
     };
+    Ok((food, buyer, currency))
+}
+
+/// The state orders get whose stored state isn't one: closed, which takes no more entries, but
+/// doesn't hide the order as done either
+pub const FALLBACK_ORDER_STATE: OrderState = OrderState::Closed;
+
+/// `None` for [`npb::OrderState::Invalid`], which no order can be in
+pub fn order_state_to_database(state: npb::OrderState) -> Option<OrderState> {
+    match state {
+        npb::OrderState::Open => Some(OrderState::Open),
+        npb::OrderState::Closed => Some(OrderState::Closed),
+        npb::OrderState::Done => Some(OrderState::Done),
+        npb::OrderState::Invalid => None,
+    }
+}
+
+/// Orders in states that aren't one are in [`FALLBACK_ORDER_STATE`]
+pub fn order_state_from_database(state: OrderState) -> npb::OrderState {
+    match state {
+        OrderState::Open => npb::OrderState::Open,
+        OrderState::Closed => npb::OrderState::Closed,
+        OrderState::Done => npb::OrderState::Done,
+        OrderState::Unknown(_) => order_state_from_database(FALLBACK_ORDER_STATE),
+    }
+}
 
-    Some(
-        napoli_server_persistent_entities::order_entry::ActiveModel {
-            id: NotSet,
-            order_id: Set(request.order_id),
-            buyer: Set(request.buyer),
-            food: Set(request.food),
-            price_in_millicents: Set(price_in_millicents.raw()),
-            paid: Set(false),
-        },
-    )
+/// For states of orders from elsewhere, which may be anything. See [`FALLBACK_ORDER_STATE`]
+pub fn database_order_state(state: i32) -> OrderState {
+    npb::OrderState::from_i32(state)
+        .and_then(order_state_to_database)
+        .unwrap_or(FALLBACK_ORDER_STATE)
+}
+
+/// The state a client asks an order to change to
+pub fn order_state_from_request(
+    field: &'static str,
+    state: i32,
+) -> Result<npb::OrderState, tonic::Status> {
+    match npb::OrderState::from_i32(state) {
+        Some(npb::OrderState::Invalid) | None => {
+            Err(validate::invalid(field, "is not a valid order state"))
+        }
+        Some(state) => Ok(state),
+    }
+}
+
+/// The price for clients that still read `price_deprecated`, which only knows euros. It's 0 for
+/// orders in another currency, which these clients would otherwise show as euros, and for broken
+/// prices, which integrity::mark reports
+pub fn price_deprecated(order_currency: &str, price_in_millicents: i64) -> f64 {
+    if Currency::from_code(order_currency) != Some(Currency::Eur) {
+        return 0.0;
+    }
+    Millicents::from_raw(price_in_millicents)
+        .map(|price| price.to_euro_float())
+        .unwrap_or(0.0)
 }
 
 pub fn database_order_to_tonic_order(
@@ -58,47 +153,36 @@ pub fn database_order_to_tonic_order(
     order_entries.sort_by_key(|entry| entry.id);
     let order_entries = order_entries.into_iter();
 
-    let timestamp = order.timestamp.unwrap_or(String::from(""));
-
-    napoli_lib::napoli::Order {
+    let stored_state = order.state;
+    let mut order = napoli_lib::napoli::Order {
         id: order.id,
         menu_url: order.menu_url,
-        state: order.state,
-        timestamp,
+        state: order_state_from_database(order.state) as i32,
+        timestamp: calendar::format(order.created_at),
+        version: order.version,
+        currency: order.currency.clone(),
         entries: order_entries
             .map(|entry| {
-                // TODO Add tainted flag to the protocol
-                let price = match napoli_lib::Millicents::from_raw(entry.price_in_millicents) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        println!("Failed to parse: {:?}", e);
-                        Millicents::zero()
-                    }
-                };
-
+                let price_deprecated = price_deprecated(&order.currency, entry.price_in_millicents);
                 napoli_lib::napoli::OrderEntry {
                     id: entry.id,
                     buyer: entry.buyer.to_owned(),
                     food: entry.food.to_owned(),
-                    price_deprecated: price.to_euro_float(),
+                    price_deprecated,
                     price_in_millicents: entry.price_in_millicents,
                     paid: entry.paid,
+                    integrity_warnings: vec![],
                 }
             })
             .collect(),
+        integrity_warnings: vec![],
+    };
+    integrity::mark(&mut order);
+    // The order is in the fallback state now, what was stored is still reported
+    if let OrderState::Unknown(state) = stored_state {
+        order
+            .integrity_warnings
+            .insert(0, integrity::state_warning(state));
     }
-}
-
-pub fn make_single_order_reply(
-    order: napoli_server_persistent_entities::order::Model,
-    mut order_entries: Vec<napoli_server_persistent_entities::order_entry::Model>,
-) -> SingleOrderReply {
-    order_entries.sort_by_key(|entry| entry.id);
-
-    SingleOrderReply {
-        order: Some(database_order_to_tonic_order(
-            order,
-            order_entries.into_iter(),
-        )),
-    }
+    order
 }
@@ -0,0 +1,760 @@
+//! Persistence of orders and their entries.
+//!
+//! The gRPC layer only talks to an [`OrderRepository`], so it can run against the sea-orm
+//! database in production, against the embedded [`KeyValueOrderRepository`] and against
+//! [`MemoryOrderRepository`] in tests. [`copy_orders`] moves all data from one to another.
+
+use napoli_lib::napoli as npb;
+use napoli_lib::Currency;
+use time::OffsetDateTime;
+
+use crate::calendar;
+
+mod database;
+mod key_value;
+mod memory;
+
+pub use database::{connect, DatabaseOrderRepository};
+pub use key_value::KeyValueOrderRepository;
+pub use memory::MemoryOrderRepository;
+
+pub type Result<T> = std::result::Result<T, RepositoryError>;
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    OrderNotFound,
+    OrderEntryNotFound,
+    OrderNotOpen,
+    /// The price of a new entry is in another currency than the order
+    CurrencyMismatch {
+        order: String,
+        entry: Currency,
+    },
+    /// Somebody else changed the order since the client last saw it
+    VersionMismatch {
+        expected: i32,
+        actual: i32,
+    },
+    Backend(String),
+}
+
+impl std::fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepositoryError::OrderNotFound => write!(f, "order not found"),
+            RepositoryError::OrderEntryNotFound => write!(f, "order entry not found"),
+            RepositoryError::OrderNotOpen => write!(f, "Order is not open"),
+            RepositoryError::CurrencyMismatch { order, entry } => {
+                write!(f, "order is in {}, not in {}", order, entry)
+            }
+            RepositoryError::VersionMismatch { expected, actual } => write!(
+                f,
+                "order is at version {}, not at version {}",
+                actual, expected
+            ),
+            RepositoryError::Backend(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+pub struct NewOrder {
+    pub menu_url: String,
+    /// See [`crate::calendar::now`]
+    pub created_at: OffsetDateTime,
+    pub currency: Currency,
+}
+
+pub struct NewOrderEntry {
+    pub order_id: i32,
+    pub buyer: String,
+    pub food: String,
+    pub price_in_millicents: i64,
+    /// `None` for the currency of the order
+    pub currency: Option<Currency>,
+}
+
+/// All operations return the affected order with all of its entries sorted by id. Every
+/// operation that changes the order or its entries increments its version.
+///
+/// Changes with an `expected_version` fail with [`RepositoryError::VersionMismatch`] if the order
+/// is at another version, even if they wouldn't change anything. Checking the version and
+/// changing the order is atomic
+#[tonic::async_trait]
+#[allow(clippy::double_must_use)]
+pub trait OrderRepository: Send + Sync {
+    /// All orders, newest first
+    async fn get_orders(&self) -> Result<Vec<npb::Order>>;
+
+    async fn get_order(&self, order_id: i32) -> Result<npb::Order>;
+
+    /// Orders created at `from` or later and before `until`, newest first
+    async fn get_orders_created_between(
+        &self,
+        from: OffsetDateTime,
+        until: OffsetDateTime,
+    ) -> Result<Vec<npb::Order>> {
+        let mut orders = self.get_orders().await?;
+        orders.retain(|order| {
+            calendar::parse(&order.timestamp)
+                .is_some_and(|created_at| from <= created_at && created_at < until)
+        });
+        Ok(orders)
+    }
+
+    /// Creates a new open order
+    async fn create_order(&self, order: NewOrder) -> Result<npb::Order>;
+
+    /// Orders can't be in [`npb::OrderState::Invalid`]
+    async fn update_order_state(
+        &self,
+        order_id: i32,
+        state: npb::OrderState,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order>;
+
+    /// Fails with [`RepositoryError::OrderNotOpen`] unless the order is open and with
+    /// [`RepositoryError::CurrencyMismatch`] if the price is in another currency than the order
+    async fn add_order_entry(
+        &self,
+        entry: NewOrderEntry,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order>;
+
+    /// Removing an entry that doesn't exist (anymore) is not an error
+    async fn remove_order_entry(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order>;
+
+    async fn set_order_entry_paid(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        paid: bool,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order>;
+
+    /// Stores the order and its entries as they are, keeping all ids. New orders and entries
+    /// get ids above the imported ones
+    async fn import_order(&self, order: npb::Order) -> Result<()>;
+}
+
+/// Fails with [`RepositoryError::VersionMismatch`] unless the order is at `expected_version`
+fn check_version(order: &npb::Order, expected_version: Option<i32>) -> Result<()> {
+    match expected_version {
+        Some(expected) if expected != order.version => Err(RepositoryError::VersionMismatch {
+            expected,
+            actual: order.version,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Fails with [`RepositoryError::CurrencyMismatch`] if `entry` is in another currency than the
+/// order, whose currency is `order_currency`
+fn check_currency(order_currency: &str, entry: Option<Currency>) -> Result<()> {
+    match entry {
+        Some(entry) if Currency::from_code(order_currency) != Some(entry) => {
+            Err(RepositoryError::CurrencyMismatch {
+                order: order_currency.to_owned(),
+                entry,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Copies all orders from `from` into the empty repository `to` and returns how many there were
+pub async fn copy_orders(from: &dyn OrderRepository, to: &dyn OrderRepository) -> Result<usize> {
+    if !to.get_orders().await?.is_empty() {
+        return Err(RepositoryError::Backend(
+            "the target storage already contains orders".to_owned(),
+        ));
+    }
+
+    let mut orders = from.get_orders().await?;
+    backfill_timestamps(&mut orders);
+    // Oldest first, so the ids come out in the same order
+    for order in orders.iter().rev() {
+        to.import_order(order.clone()).await?;
+    }
+    Ok(orders.len())
+}
+
+/// Gives orders without a valid timestamp, which were created before orders had one, the
+/// timestamp of the next newer order that has one, like the migration to a datetime column does.
+/// `orders` are newest first
+fn backfill_timestamps(orders: &mut [npb::Order]) {
+    let mut next_newer = calendar::now();
+    for order in orders {
+        match calendar::parse(&order.timestamp) {
+            Some(created_at) => next_newer = created_at,
+            None => order.timestamp = calendar::format(next_newer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use napoli_server_migrations::{Migrator, MigratorTrait};
+    use sea_orm::ConnectionTrait;
+    use std::sync::Arc;
+
+    async fn database_repository() -> DatabaseOrderRepository {
+        let db = connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        DatabaseOrderRepository::new(db)
+    }
+
+    fn key_value_repository() -> KeyValueOrderRepository {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        KeyValueOrderRepository::with_db(db).unwrap()
+    }
+
+    fn new_entry(order_id: i32, buyer: &str) -> NewOrderEntry {
+        NewOrderEntry {
+            order_id,
+            buyer: buyer.to_owned(),
+            food: "Bufala".to_owned(),
+            price_in_millicents: 1050000,
+            currency: None,
+        }
+    }
+
+    /// Both implementations have to behave the same
+    async fn order_lifecycle(repository: &dyn OrderRepository) {
+        let order = repository
+            .create_order(NewOrder {
+                menu_url: "https://napoli.example".to_owned(),
+                created_at: time::macros::datetime!(2023-04-25 20:51 UTC),
+                currency: Currency::Eur,
+            })
+            .await
+            .unwrap();
+        assert_eq!(order.state, npb::OrderState::Open as i32);
+        assert!(order.entries.is_empty());
+
+        repository
+            .add_order_entry(new_entry(order.id, "Rob"), None)
+            .await
+            .unwrap();
+        let with_entries = repository
+            .add_order_entry(new_entry(order.id, "Hauke"), None)
+            .await
+            .unwrap();
+        let buyers: Vec<_> = with_entries
+            .entries
+            .iter()
+            .map(|e| e.buyer.as_str())
+            .collect();
+        assert_eq!(buyers, ["Rob", "Hauke"]);
+        assert_eq!(with_entries.entries[0].price_deprecated, 10.5);
+
+        let rob = with_entries.entries[0].id;
+        let paid = repository
+            .set_order_entry_paid(order.id, rob, true, None)
+            .await
+            .unwrap();
+        assert!(paid.entries[0].paid);
+        assert!(!paid.entries[1].paid);
+
+        let removed = repository
+            .remove_order_entry(order.id, rob, None)
+            .await
+            .unwrap();
+        assert_eq!(removed.entries.len(), 1);
+        assert_eq!(removed.entries[0].buyer, "Hauke");
+
+        // Every change counts, doing nothing doesn't
+        assert_eq!(removed.version, order.version + 4);
+        let unchanged = repository
+            .remove_order_entry(order.id, rob, None)
+            .await
+            .unwrap();
+        assert_eq!(unchanged.version, removed.version);
+
+        let closed = repository
+            .update_order_state(order.id, npb::OrderState::Closed, None)
+            .await
+            .unwrap();
+        assert_eq!(closed.state, npb::OrderState::Closed as i32);
+        assert!(matches!(
+            repository
+                .add_order_entry(new_entry(order.id, "Max"), None)
+                .await,
+            Err(RepositoryError::OrderNotOpen)
+        ));
+
+        assert!(matches!(
+            repository.get_order(order.id + 1).await,
+            Err(RepositoryError::OrderNotFound)
+        ));
+        assert!(matches!(
+            repository
+                .set_order_entry_paid(order.id, rob, true, None)
+                .await,
+            Err(RepositoryError::OrderEntryNotFound)
+        ));
+
+        let second = repository
+            .create_order(NewOrder {
+                menu_url: "https://pizza.example".to_owned(),
+                created_at: calendar::now(),
+                currency: Currency::Eur,
+            })
+            .await
+            .unwrap();
+        let ids: Vec<_> = repository
+            .get_orders()
+            .await
+            .unwrap()
+            .iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(ids, [second.id, order.id]);
+    }
+
+    async fn expected_versions(repository: &dyn OrderRepository) {
+        let order = repository
+            .create_order(NewOrder {
+                menu_url: "https://napoli.example".to_owned(),
+                created_at: calendar::now(),
+                currency: Currency::Eur,
+            })
+            .await
+            .unwrap();
+        let added = repository
+            .add_order_entry(new_entry(order.id, "Rob"), Some(order.version))
+            .await
+            .unwrap();
+        let rob = added.entries[0].id;
+
+        // The second of two clients who both saw `added` loses
+        let paid = repository
+            .set_order_entry_paid(order.id, rob, true, Some(added.version))
+            .await
+            .unwrap();
+        assert!(matches!(
+            repository
+                .set_order_entry_paid(order.id, rob, false, Some(added.version))
+                .await,
+            Err(RepositoryError::VersionMismatch { expected, actual })
+                if expected == added.version && actual == paid.version
+        ));
+        assert!(matches!(
+            repository
+                .update_order_state(order.id, npb::OrderState::Closed, Some(added.version))
+                .await,
+            Err(RepositoryError::VersionMismatch { .. })
+        ));
+        assert!(matches!(
+            repository
+                .remove_order_entry(order.id, rob, Some(order.version))
+                .await,
+            Err(RepositoryError::VersionMismatch { .. })
+        ));
+        assert!(matches!(
+            repository
+                .add_order_entry(new_entry(order.id, "Hauke"), Some(order.version))
+                .await,
+            Err(RepositoryError::VersionMismatch { .. })
+        ));
+        assert_eq!(repository.get_order(order.id).await.unwrap(), paid);
+
+        // Doing nothing at the right version succeeds without a new version
+        let unchanged = repository
+            .set_order_entry_paid(order.id, rob, true, Some(paid.version))
+            .await
+            .unwrap();
+        assert_eq!(unchanged, paid);
+        let closed = repository
+            .update_order_state(order.id, npb::OrderState::Closed, Some(paid.version))
+            .await
+            .unwrap();
+        assert_eq!(closed.version, paid.version + 1);
+    }
+
+    async fn created_between(repository: &dyn OrderRepository) {
+        let mut ids = vec![];
+        for created_at in [
+            time::macros::datetime!(2026-10-18 21:59:59 UTC),
+            time::macros::datetime!(2026-10-18 22:00 UTC),
+            time::macros::datetime!(2026-10-19 21:59:59.9 UTC),
+            time::macros::datetime!(2026-10-19 22:00 UTC),
+        ] {
+            let order = repository
+                .create_order(NewOrder {
+                    menu_url: "https://napoli.example".to_owned(),
+                    created_at,
+                    currency: Currency::Eur,
+                })
+                .await
+                .unwrap();
+            ids.push(order.id);
+        }
+
+        // 2026-10-19 in Berlin
+        let orders = repository
+            .get_orders_created_between(
+                time::macros::datetime!(2026-10-19 00:00 +02:00),
+                time::macros::datetime!(2026-10-20 00:00 +02:00),
+            )
+            .await
+            .unwrap();
+        let found: Vec<_> = orders.iter().map(|order| order.id).collect();
+        assert_eq!(found, [ids[2], ids[1]]);
+        assert_eq!(orders[1].timestamp, "2026-10-18T22:00:00Z");
+        assert_eq!(orders[0].timestamp, "2026-10-19T21:59:59Z");
+    }
+
+    async fn currencies(repository: &dyn OrderRepository) {
+        let order = repository
+            .create_order(NewOrder {
+                menu_url: "https://zurich.example".to_owned(),
+                created_at: calendar::now(),
+                currency: Currency::Chf,
+            })
+            .await
+            .unwrap();
+        assert_eq!(order.currency, "CHF");
+
+        let in_francs = NewOrderEntry {
+            currency: Some(Currency::Chf),
+            ..new_entry(order.id, "Rob")
+        };
+        let added = repository.add_order_entry(in_francs, None).await.unwrap();
+        assert_eq!(added.currency, "CHF");
+        assert_eq!(added.entries.len(), 1);
+        // Clients of the deprecated float would take it for euros
+        assert_eq!(added.entries[0].price_deprecated, 0.0);
+        repository
+            .add_order_entry(new_entry(order.id, "Hauke"), None)
+            .await
+            .unwrap();
+
+        let in_euros = NewOrderEntry {
+            currency: Some(Currency::Eur),
+            ..new_entry(order.id, "Max")
+        };
+        assert!(matches!(
+            repository.add_order_entry(in_euros, None).await,
+            Err(RepositoryError::CurrencyMismatch { order, entry: Currency::Eur }) if order == "CHF"
+        ));
+        assert_eq!(
+            repository.get_order(order.id).await.unwrap().entries.len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_order_lifecycle() {
+        order_lifecycle(&MemoryOrderRepository::new()).await;
+        expected_versions(&MemoryOrderRepository::new()).await;
+        currencies(&MemoryOrderRepository::new()).await;
+        created_between(&MemoryOrderRepository::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_order_lifecycle() {
+        order_lifecycle(&database_repository().await).await;
+        expected_versions(&database_repository().await).await;
+        currencies(&database_repository().await).await;
+        created_between(&database_repository().await).await;
+    }
+
+    /// Orders from before orders had a time get the time of the next newer order
+    #[tokio::test]
+    async fn old_orders_get_a_creation_time() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        // Up to m20261019_093000_add_currency_to_order
+        Migrator::up(&db, Some(9)).await.unwrap();
+        db.execute_unprepared(
+            "INSERT INTO \"order\" (id, menu_url, state, timestamp, version, currency) \
+             VALUES (1, '', 3, NULL, 1, 'EUR'), \
+                    (2, '', 3, '', 1, 'EUR'), \
+                    (3, '', 3, '2024-11-27T11:45:12.123+01:00', 1, 'EUR'), \
+                    (4, '', 3, 'yesterday', 1, 'EUR'), \
+                    (5, '', 3, '2025-02-04T12:00:00Z', 1, 'EUR');",
+        )
+        .await
+        .unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let database = DatabaseOrderRepository::new(db);
+
+        let timestamps: Vec<_> = database
+            .get_orders()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|order| order.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            [
+                "2025-02-04T12:00:00Z",
+                "2025-02-04T12:00:00Z",
+                "2024-11-27T10:45:12Z",
+                "2024-11-27T10:45:12Z",
+                "2024-11-27T10:45:12Z",
+            ]
+        );
+    }
+
+    /// Orders with states that aren't one are closed, by the migration and on import
+    #[tokio::test]
+    async fn invalid_states_are_closed() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        // Up to m20261019_103000_order_created_at
+        Migrator::up(&db, Some(10)).await.unwrap();
+        db.execute_unprepared(
+            "INSERT INTO \"order\" (id, menu_url, state, version, currency) \
+             VALUES (1, '', 0, 1, 'EUR'), \
+                    (2, '', 1, 1, 'EUR'), \
+                    (3, '', 7, 1, 'EUR'), \
+                    (4, '', 3, 1, 'EUR');",
+        )
+        .await
+        .unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let database = DatabaseOrderRepository::new(db);
+
+        let states: Vec<_> = database
+            .get_orders()
+            .await
+            .unwrap()
+            .iter()
+            .map(|order| order.state())
+            .collect();
+        use npb::OrderState::{Closed, Done, Open};
+        assert_eq!(states, [Done, Closed, Open, Closed]);
+
+        let mut order = napoli_lib::create_example_order();
+        order.id = 5;
+        order.state = 9;
+        order.timestamp = "2026-10-19T10:00:00Z".to_owned();
+        database.import_order(order).await.unwrap();
+        assert_eq!(database.get_order(5).await.unwrap().state(), Closed);
+        assert!(matches!(
+            database
+                .update_order_state(5, npb::OrderState::Invalid, None)
+                .await,
+            Err(RepositoryError::Backend(_))
+        ));
+    }
+
+    /// A state written behind the server's back after the migration doesn't break reading the
+    /// other orders: the order is closed and the stored state reported
+    #[tokio::test]
+    async fn unknown_states_are_closed_when_read() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let database = DatabaseOrderRepository::new(db.clone());
+        for _ in 0..2 {
+            database
+                .create_order(NewOrder {
+                    menu_url: "https://napoli.example".to_owned(),
+                    created_at: calendar::now(),
+                    currency: Currency::Eur,
+                })
+                .await
+                .unwrap();
+        }
+        db.execute_unprepared("UPDATE \"order\" SET state = 8 WHERE id = 1")
+            .await
+            .unwrap();
+
+        let orders = database.get_orders().await.unwrap();
+        assert_eq!(orders.len(), 2);
+        let order = database.get_order(1).await.unwrap();
+        assert_eq!(order.state(), npb::OrderState::Closed);
+        assert_eq!(
+            order.integrity_warnings,
+            ["state 8 is not a valid order state"]
+        );
+        let tainted = crate::integrity::tainted_orders(&database).await.unwrap();
+        assert_eq!(tainted, [order]);
+        assert!(matches!(
+            database.add_order_entry(new_entry(1, "Rob"), None).await,
+            Err(RepositoryError::OrderNotOpen)
+        ));
+    }
+
+    /// Entries can't outlive their order anymore, and those from before are found
+    #[tokio::test]
+    async fn orphaned_entries() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        // Up to m20261019_113000_repair_order_state, without foreign keys like old databases
+        Migrator::up(&db, Some(11)).await.unwrap();
+        db.execute_unprepared(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO \"order\" (id, menu_url, state, version, currency) \
+             VALUES (1, '', 1, 1, 'EUR'), (2, '', 1, 1, 'EUR');
+             INSERT INTO order_entry (id, order_id, buyer, food, price_in_millicents, paid) \
+             VALUES (1, 1, 'Rob', 'Bufala', 1050000, false), \
+                    (2, 99, 'Hauke', 'Bufala', 1050000, false), \
+                    (3, 2, 'Max', 'Bufala', 1050000, false);
+             PRAGMA foreign_keys = ON;",
+        )
+        .await
+        .unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        // Enforced for the server's connections
+        assert!(db
+            .execute_unprepared(
+                "INSERT INTO order_entry (order_id, buyer, food, price_in_millicents, paid) \
+                 VALUES (98, 'Max', 'Bufala', 1050000, false)"
+            )
+            .await
+            .is_err());
+        // Deleting an order deletes its entries
+        db.execute_unprepared("DELETE FROM \"order\" WHERE id = 2")
+            .await
+            .unwrap();
+
+        let database = DatabaseOrderRepository::new(db);
+        assert_eq!(database.orphaned_entries().await.unwrap(), [2]);
+        assert_eq!(database.delete_orphaned_entries().await.unwrap(), 1);
+        assert!(database.orphaned_entries().await.unwrap().is_empty());
+        let order = database.get_order(1).await.unwrap();
+        assert_eq!(order.entries.len(), 1);
+
+        // Ids of the deleted entries aren't handed out again
+        let added = database
+            .add_order_entry(new_entry(1, "Felix"), None)
+            .await
+            .unwrap();
+        assert_eq!(added.entries[1].id, 4);
+    }
+
+    /// Rows written behind the server's back are reported, also after copying them elsewhere
+    #[tokio::test]
+    async fn broken_rows_are_reported() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        db.execute_unprepared(
+            "INSERT INTO \"order\" (id, menu_url, state, version, currency) \
+             VALUES (1, '', 1, 1, 'EUR');
+             INSERT INTO order_entry (id, order_id, buyer, food, price_in_millicents, paid) \
+             VALUES (1, 1, 'Rob', 'Bufala', 1050000, false), \
+                    (2, 1, 'Hauke', 'Bufala', -1050000, false);",
+        )
+        .await
+        .unwrap();
+        let database = DatabaseOrderRepository::new(db);
+
+        let order = database.get_order(1).await.unwrap();
+        assert!(!order.entries[0].is_tainted());
+        assert_eq!(order.entries[1].price_deprecated, 0.0);
+        assert_eq!(
+            order.integrity_warnings,
+            ["entry 2: price_in_millicents -1050000 is negative"]
+        );
+        let tainted = crate::integrity::tainted_orders(&database).await.unwrap();
+        assert_eq!(tainted, std::slice::from_ref(&order));
+
+        for copy in [
+            Box::new(MemoryOrderRepository::new()) as Box<dyn OrderRepository>,
+            Box::new(key_value_repository()),
+        ] {
+            copy_orders(&database, copy.as_ref()).await.unwrap();
+            assert_eq!(copy.get_order(1).await.unwrap(), order);
+            let fixed = copy.remove_order_entry(1, 2, None).await.unwrap();
+            assert!(!fixed.is_tainted());
+        }
+    }
+
+    /// An entry must never slip into an order after it was closed, even if it was added while
+    /// the order was being closed
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn add_order_entry_races_close() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file, which gets a pool of connections that change the order at the same time
+        let path = dir.path().join("napoli.sqlite");
+        let db = connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repository = Arc::new(DatabaseOrderRepository::new(db));
+
+        for _ in 0..10 {
+            let order = repository
+                .create_order(NewOrder {
+                    menu_url: "https://napoli.example".to_owned(),
+                    created_at: calendar::now(),
+                    currency: Currency::Eur,
+                })
+                .await
+                .unwrap();
+
+            let adds: Vec<_> = (0..20)
+                .map(|i| {
+                    let repository = repository.clone();
+                    tokio::spawn(async move {
+                        let buyer = format!("Buyer {}", i);
+                        let added = repository
+                            .add_order_entry(new_entry(order.id, &buyer), None)
+                            .await;
+                        (buyer, added)
+                    })
+                })
+                .collect();
+            let closed = repository
+                .update_order_state(order.id, npb::OrderState::Closed, None)
+                .await
+                .unwrap();
+
+            for add in adds {
+                match add.await.unwrap() {
+                    (buyer, Ok(added)) => {
+                        assert_eq!(added.state, npb::OrderState::Open as i32);
+                        assert!(closed.entries.iter().any(|entry| entry.buyer == buyer));
+                    }
+                    (_, Err(RepositoryError::OrderNotOpen)) => {}
+                    (_, Err(err)) => panic!("add_order_entry failed: {}", err),
+                }
+            }
+            assert_eq!(repository.get_order(order.id).await.unwrap(), closed);
+        }
+    }
+
+    #[tokio::test]
+    async fn key_value_order_lifecycle() {
+        order_lifecycle(&key_value_repository()).await;
+        expected_versions(&key_value_repository()).await;
+        currencies(&key_value_repository()).await;
+        created_between(&key_value_repository()).await;
+    }
+
+    #[tokio::test]
+    async fn copy_orders_keeps_ids() {
+        let database = database_repository().await;
+        order_lifecycle(&database).await;
+        let orders = database.get_orders().await.unwrap();
+
+        let key_value = key_value_repository();
+        assert_eq!(copy_orders(&database, &key_value).await.unwrap(), 2);
+        assert_eq!(key_value.get_orders().await.unwrap(), orders);
+
+        let copied_back = database_repository().await;
+        copy_orders(&key_value, &copied_back).await.unwrap();
+        assert_eq!(copied_back.get_orders().await.unwrap(), orders);
+
+        // Ids continue after the imported ones
+        let order = key_value
+            .create_order(NewOrder {
+                menu_url: "https://napoli.example".to_owned(),
+                created_at: calendar::now(),
+                currency: Currency::Eur,
+            })
+            .await
+            .unwrap();
+        assert_eq!(order.id, orders[0].id + 1);
+        assert!(copy_orders(&database, &key_value).await.is_err());
+    }
+}
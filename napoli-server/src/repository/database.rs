@@ -0,0 +1,392 @@
+use napoli_lib::napoli as npb;
+use napoli_lib::Currency;
+use napoli_server_persistent_entities::order;
+use napoli_server_persistent_entities::order_entry;
+use napoli_server_persistent_entities::sea_orm_active_enums::OrderState;
+use sea_orm::sea_query::{Expr, Query, SimpleExpr};
+use sea_orm::ActiveValue::NotSet;
+use sea_orm::EntityTrait;
+use sea_orm::{ActiveModelTrait, ColumnTrait, IntoActiveModel, QueryFilter, Set};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, DatabaseTransaction};
+use sea_orm::{DbErr, RuntimeErr, SqlxSqliteConnector};
+use sea_orm::{QueryOrder as _, TransactionTrait};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use std::str::FromStr;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use super::{check_currency, NewOrder, NewOrderEntry, OrderRepository, RepositoryError, Result};
+use crate::calendar;
+use crate::model_adapters;
+
+/// Stores orders in the SQLite or Postgres database through sea-orm. Every change runs in its
+/// own transaction
+pub struct DatabaseOrderRepository {
+    db_handle: DatabaseConnection,
+}
+
+impl DatabaseOrderRepository {
+    pub fn new(db_handle: DatabaseConnection) -> Self {
+        DatabaseOrderRepository { db_handle }
+    }
+
+    /// Ids of the entries whose order doesn't exist, which databases that didn't enforce foreign
+    /// keys may have
+    pub async fn orphaned_entries(&self) -> Result<Vec<i32>> {
+        let entries = order_entry::Entity::find()
+            .filter(orphaned())
+            .order_by(order_entry::Column::Id, sea_orm::Order::Asc)
+            .all(&self.db_handle)
+            .await
+            .map_err(backend_error)?;
+        Ok(entries.into_iter().map(|entry| entry.id).collect())
+    }
+
+    /// Deletes the entries whose order doesn't exist, like deleting the order would have if the
+    /// database had enforced foreign keys. Returns how many there were
+    pub async fn delete_orphaned_entries(&self) -> Result<u64> {
+        let deleted = order_entry::Entity::delete_many()
+            .filter(orphaned())
+            .exec(&self.db_handle)
+            .await
+            .map_err(backend_error)?;
+        Ok(deleted.rows_affected)
+    }
+
+    /// Starts a change of the order: increments its version, which takes the write lock on the
+    /// order until the transaction ends, and returns the order with the new version. Rolling
+    /// back the transaction undoes the increment, for changes that turn out not to change anything
+    async fn begin_change(
+        &self,
+        order_id: i32,
+        expected_version: Option<i32>,
+    ) -> Result<(DatabaseTransaction, order::Model)> {
+        let txn = self.db_handle.begin().await.map_err(backend_error)?;
+
+        let mut increment = order::Entity::update_many()
+            .col_expr(
+                order::Column::Version,
+                Expr::col(order::Column::Version).add(1),
+            )
+            .filter(order::Column::Id.eq(order_id));
+        if let Some(expected) = expected_version {
+            increment = increment.filter(order::Column::Version.eq(expected));
+        }
+        let incremented = increment.exec(&txn).await.map_err(backend_error)?;
+
+        let order = order::Entity::find_by_id(order_id)
+            .one(&txn)
+            .await
+            .map_err(backend_error)?
+            .ok_or(RepositoryError::OrderNotFound)?;
+        match expected_version {
+            Some(expected) if incremented.rows_affected == 0 => {
+                Err(RepositoryError::VersionMismatch {
+                    expected,
+                    actual: order.version,
+                })
+            }
+            _ => Ok((txn, order)),
+        }
+    }
+
+    /// Commits the change and returns the changed order
+    async fn commit_change(&self, txn: DatabaseTransaction, order_id: i32) -> Result<npb::Order> {
+        let order = find_order(&txn, order_id).await?;
+        txn.commit().await.map_err(backend_error)?;
+        Ok(order)
+    }
+
+    /// Rolls back a change that didn't change anything and returns the order as it is
+    async fn discard_change(&self, txn: DatabaseTransaction, order_id: i32) -> Result<npb::Order> {
+        txn.rollback().await.map_err(backend_error)?;
+        find_order(&self.db_handle, order_id).await
+    }
+}
+
+/// Entries whose order doesn't exist
+fn orphaned() -> SimpleExpr {
+    order_entry::Column::OrderId.not_in_subquery(
+        Query::select()
+            .column(order::Column::Id)
+            .from(order::Entity)
+            .to_owned(),
+    )
+}
+
+async fn find_order(db: &impl ConnectionTrait, order_id: i32) -> Result<npb::Order> {
+    let orders = order::Entity::find_by_id(order_id)
+        .find_with_related(order_entry::Entity)
+        .all(db)
+        .await
+        .map_err(backend_error)?;
+
+    match orders.into_iter().next() {
+        Some((order, entries)) => Ok(model_adapters::database_order_to_tonic_order(
+            order,
+            entries.into_iter(),
+        )),
+        None => Err(RepositoryError::OrderNotFound),
+    }
+}
+
+/// Connects to the database at `url`, any sea-orm connection URL. SQLite only enforces foreign
+/// keys on connections that ask for it, so SQLite connections do
+pub async fn connect(url: &str) -> std::result::Result<DatabaseConnection, DbErr> {
+    if !url.starts_with("sqlite:") {
+        return sea_orm::Database::connect(url).await;
+    }
+    let options = SqliteConnectOptions::from_str(url)
+        .map_err(|err| DbErr::Conn(RuntimeErr::SqlxError(err)))?
+        .foreign_keys(true);
+    let pool = if url.contains(":memory:") || url.contains("mode=memory") {
+        // Every connection would get its own empty database
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+    } else {
+        // Readers don't wait for the writer, and writers wait for each other instead of failing
+        SqlitePoolOptions::new()
+            .connect_with(
+                options
+                    .journal_mode(SqliteJournalMode::Wal)
+                    .busy_timeout(Duration::from_secs(5)),
+            )
+            .await
+    };
+    let pool = pool.map_err(|err| DbErr::Conn(RuntimeErr::SqlxError(err)))?;
+    Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool))
+}
+
+fn backend_error(err: sea_orm::DbErr) -> RepositoryError {
+    RepositoryError::Backend(err.to_string())
+}
+
+#[tonic::async_trait]
+impl OrderRepository for DatabaseOrderRepository {
+    async fn get_orders(&self) -> Result<Vec<npb::Order>> {
+        let orders = order::Entity::find()
+            .order_by(order::Column::Id, sea_orm::Order::Desc)
+            .find_with_related(order_entry::Entity)
+            .all(&self.db_handle)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(orders
+            .into_iter()
+            .map(|(order, entries)| {
+                model_adapters::database_order_to_tonic_order(order, entries.into_iter())
+            })
+            .collect())
+    }
+
+    async fn get_order(&self, order_id: i32) -> Result<npb::Order> {
+        find_order(&self.db_handle, order_id).await
+    }
+
+    async fn get_orders_created_between(
+        &self,
+        from: OffsetDateTime,
+        until: OffsetDateTime,
+    ) -> Result<Vec<npb::Order>> {
+        let orders = order::Entity::find()
+            .filter(order::Column::CreatedAt.gte(calendar::normalized(from)))
+            .filter(order::Column::CreatedAt.lt(calendar::normalized(until)))
+            .order_by(order::Column::Id, sea_orm::Order::Desc)
+            .find_with_related(order_entry::Entity)
+            .all(&self.db_handle)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(orders
+            .into_iter()
+            .map(|(order, entries)| {
+                model_adapters::database_order_to_tonic_order(order, entries.into_iter())
+            })
+            .collect())
+    }
+
+    async fn create_order(&self, new_order: NewOrder) -> Result<npb::Order> {
+        let order = order::ActiveModel {
+            id: NotSet,
+            menu_url: Set(new_order.menu_url),
+            // You can replace with: #[sea_orm(default_value="1")] in the model definition,
+            // but loose the ability to use the enum directly there, this is why we do it here
+            state: Set(OrderState::Open),
+            created_at: Set(calendar::normalized(new_order.created_at)),
+            version: Set(1),
+            currency: Set(new_order.currency.code().to_owned()),
+        };
+
+        let order = order.insert(&self.db_handle).await.map_err(backend_error)?;
+        Ok(model_adapters::database_order_to_tonic_order(
+            order,
+            std::iter::empty(),
+        ))
+    }
+
+    async fn update_order_state(
+        &self,
+        order_id: i32,
+        state: npb::OrderState,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let state = model_adapters::order_state_to_database(state).ok_or_else(|| {
+            RepositoryError::Backend(format!("orders can't be in state {:?}", state))
+        })?;
+        let (txn, order) = self.begin_change(order_id, expected_version).await?;
+        if order.state == state {
+            return self.discard_change(txn, order_id).await;
+        }
+
+        order::Entity::update_many()
+            .col_expr(order::Column::State, Expr::value(state))
+            .filter(order::Column::Id.eq(order_id))
+            .exec(&txn)
+            .await
+            .map_err(backend_error)?;
+
+        self.commit_change(txn, order_id).await
+    }
+
+    async fn add_order_entry(
+        &self,
+        entry: NewOrderEntry,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        // The order is locked from here on, so it can't be closed before the entry is in
+        let (txn, order) = self.begin_change(entry.order_id, expected_version).await?;
+        if order.state != OrderState::Open {
+            txn.rollback().await.map_err(backend_error)?;
+            return Err(RepositoryError::OrderNotOpen);
+        }
+        if let Err(err) = check_currency(&order.currency, entry.currency) {
+            txn.rollback().await.map_err(backend_error)?;
+            return Err(err);
+        }
+
+        order_entry::ActiveModel {
+            id: NotSet,
+            order_id: Set(entry.order_id),
+            buyer: Set(entry.buyer),
+            food: Set(entry.food),
+            price_in_millicents: Set(entry.price_in_millicents),
+            paid: Set(false),
+        }
+        .insert(&txn)
+        .await
+        .map_err(backend_error)?;
+
+        self.commit_change(txn, entry.order_id).await
+    }
+
+    async fn remove_order_entry(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let (txn, _) = self.begin_change(order_id, expected_version).await?;
+        let deleted = order_entry::Entity::delete_many()
+            .filter(order_entry::Column::Id.eq(order_entry_id))
+            .filter(order_entry::Column::OrderId.eq(order_id))
+            .exec(&txn)
+            .await
+            .map_err(backend_error)?;
+        if deleted.rows_affected == 0 {
+            return self.discard_change(txn, order_id).await;
+        }
+
+        self.commit_change(txn, order_id).await
+    }
+
+    async fn set_order_entry_paid(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        paid: bool,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let (txn, _) = self
+            .begin_change(order_id, expected_version)
+            .await
+            .map_err(|err| match err {
+                RepositoryError::OrderNotFound => RepositoryError::OrderEntryNotFound,
+                err => err,
+            })?;
+        let order_entry = order_entry::Entity::find_by_id(order_entry_id)
+            .filter(order_entry::Column::OrderId.eq(order_id))
+            .one(&txn)
+            .await
+            .map_err(backend_error)?;
+        let order_entry = match order_entry {
+            Some(order_entry) => order_entry,
+            None => {
+                txn.rollback().await.map_err(backend_error)?;
+                return Err(RepositoryError::OrderEntryNotFound);
+            }
+        };
+        if order_entry.paid == paid {
+            return self.discard_change(txn, order_id).await;
+        }
+
+        let mut order_entry = order_entry.into_active_model();
+        order_entry.paid = Set(paid);
+        order_entry.update(&txn).await.map_err(backend_error)?;
+
+        self.commit_change(txn, order_id).await
+    }
+
+    async fn import_order(&self, order: npb::Order) -> Result<()> {
+        let txn = self.db_handle.begin().await.map_err(backend_error)?;
+
+        order::ActiveModel {
+            id: Set(order.id),
+            menu_url: Set(order.menu_url),
+            state: Set(model_adapters::database_order_state(order.state)),
+            created_at: Set(calendar::parse(&order.timestamp).ok_or_else(|| {
+                RepositoryError::Backend(format!(
+                    "order {} has no valid timestamp: {:?}",
+                    order.id, order.timestamp
+                ))
+            })?),
+            version: Set(order.version),
+            currency: Set(match order.currency.as_str() {
+                "" => Currency::default().code().to_owned(),
+                currency => currency.to_owned(),
+            }),
+        }
+        .insert(&txn)
+        .await
+        .map_err(backend_error)?;
+
+        for entry in order.entries {
+            order_entry::ActiveModel {
+                id: Set(entry.id),
+                order_id: Set(order.id),
+                buyer: Set(entry.buyer),
+                food: Set(entry.food),
+                price_in_millicents: Set(entry.price_in_millicents),
+                paid: Set(entry.paid),
+            }
+            .insert(&txn)
+            .await
+            .map_err(backend_error)?;
+        }
+
+        // Postgres doesn't advance the id sequences for explicitly inserted ids
+        if txn.get_database_backend() == DatabaseBackend::Postgres {
+            for table in ["order", "order_entry"] {
+                txn.execute_unprepared(&format!(
+                    "SELECT setval(pg_get_serial_sequence('\"{table}\"', 'id'), \
+                     (SELECT MAX(id) FROM \"{table}\"))"
+                ))
+                .await
+                .map_err(backend_error)?;
+            }
+        }
+
+        txn.commit().await.map_err(backend_error)
+    }
+}
@@ -0,0 +1,262 @@
+use napoli_lib::napoli as npb;
+use prost::Message;
+use sled::transaction::{
+    ConflictableTransactionError, TransactionError, Transactional, TransactionalTree,
+};
+
+use super::{
+    check_currency, check_version, NewOrder, NewOrderEntry, OrderRepository, RepositoryError,
+    Result,
+};
+use crate::calendar;
+use crate::integrity;
+use crate::model_adapters::price_deprecated;
+
+const LAST_ORDER_ID: &[u8] = b"last_order_id";
+const LAST_ORDER_ENTRY_ID: &[u8] = b"last_order_entry_id";
+
+/// Stores every order with its entries as one protobuf message in an embedded sled database,
+/// so there is no schema to migrate
+pub struct KeyValueOrderRepository {
+    orders: sled::Tree,
+    counters: sled::Tree,
+}
+
+type TxResult<T> = std::result::Result<T, ConflictableTransactionError<RepositoryError>>;
+
+impl KeyValueOrderRepository {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::with_db(sled::open(path).map_err(backend_error)?)
+    }
+
+    pub fn with_db(db: sled::Db) -> Result<Self> {
+        Ok(KeyValueOrderRepository {
+            orders: db.open_tree("orders").map_err(backend_error)?,
+            counters: db.open_tree("counters").map_err(backend_error)?,
+        })
+    }
+
+    /// Runs `f` in a transaction over both trees
+    fn transaction<T>(
+        &self,
+        f: impl Fn(&TransactionalTree, &TransactionalTree) -> TxResult<T>,
+    ) -> Result<T> {
+        (&self.orders, &self.counters)
+            .transaction(|(orders, counters)| f(orders, counters))
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => backend_error(err),
+            })
+    }
+
+    /// Applies `f` to the stored order and writes it back with the next version, unless `f`
+    /// didn't change anything
+    fn update_order(
+        &self,
+        order_id: i32,
+        expected_version: Option<i32>,
+        f: impl Fn(&mut npb::Order) -> Result<()>,
+    ) -> Result<npb::Order> {
+        self.transaction(|orders, _| {
+            let mut order = read_order(orders, order_id)?;
+            check_version(&order, expected_version).map_err(abort)?;
+            let unchanged = order.clone();
+            f(&mut order).map_err(ConflictableTransactionError::Abort)?;
+            integrity::mark(&mut order);
+            if order != unchanged {
+                order.version += 1;
+                write_order(orders, &order)?;
+            }
+            Ok(order)
+        })
+    }
+}
+
+fn backend_error(err: impl std::fmt::Display) -> RepositoryError {
+    RepositoryError::Backend(err.to_string())
+}
+
+fn abort(err: RepositoryError) -> ConflictableTransactionError<RepositoryError> {
+    ConflictableTransactionError::Abort(err)
+}
+
+// Big endian, so the trees are sorted by id
+fn order_key(order_id: i32) -> [u8; 4] {
+    order_id.to_be_bytes()
+}
+
+/// Orders imported from other storages may carry broken values, their warnings are kept up to date
+fn decode_order(bytes: &[u8]) -> Result<npb::Order> {
+    let mut order = npb::Order::decode(bytes).map_err(backend_error)?;
+    integrity::mark(&mut order);
+    Ok(order)
+}
+
+fn read_order(orders: &TransactionalTree, order_id: i32) -> TxResult<npb::Order> {
+    match orders.get(order_key(order_id))? {
+        Some(bytes) => decode_order(&bytes).map_err(abort),
+        None => Err(abort(RepositoryError::OrderNotFound)),
+    }
+}
+
+fn write_order(orders: &TransactionalTree, order: &npb::Order) -> TxResult<()> {
+    orders.insert(&order_key(order.id), order.encode_to_vec())?;
+    Ok(())
+}
+
+/// Increments the counter and returns the new value
+fn next_id(counters: &TransactionalTree, counter: &[u8]) -> TxResult<i32> {
+    let id = read_counter(counters, counter)? + 1;
+    counters.insert(counter, &id.to_be_bytes())?;
+    Ok(id)
+}
+
+fn read_counter(counters: &TransactionalTree, counter: &[u8]) -> TxResult<i32> {
+    match counters.get(counter)? {
+        Some(bytes) => {
+            let bytes = bytes
+                .as_ref()
+                .try_into()
+                .map_err(|_| abort(backend_error("corrupt counter")))?;
+            Ok(i32::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+#[tonic::async_trait]
+impl OrderRepository for KeyValueOrderRepository {
+    async fn get_orders(&self) -> Result<Vec<npb::Order>> {
+        self.orders
+            .iter()
+            .values()
+            .rev()
+            .map(|bytes| decode_order(&bytes.map_err(backend_error)?))
+            .collect()
+    }
+
+    async fn get_order(&self, order_id: i32) -> Result<npb::Order> {
+        match self
+            .orders
+            .get(order_key(order_id))
+            .map_err(backend_error)?
+        {
+            Some(bytes) => decode_order(&bytes),
+            None => Err(RepositoryError::OrderNotFound),
+        }
+    }
+
+    async fn create_order(&self, new_order: NewOrder) -> Result<npb::Order> {
+        self.transaction(|orders, counters| {
+            let order = npb::Order {
+                id: next_id(counters, LAST_ORDER_ID)?,
+                menu_url: new_order.menu_url.clone(),
+                state: npb::OrderState::Open as i32,
+                entries: vec![],
+                timestamp: calendar::format(new_order.created_at),
+                version: 1,
+                currency: new_order.currency.code().to_owned(),
+                integrity_warnings: vec![],
+            };
+            write_order(orders, &order)?;
+            Ok(order)
+        })
+    }
+
+    async fn update_order_state(
+        &self,
+        order_id: i32,
+        state: npb::OrderState,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        self.update_order(order_id, expected_version, |order| {
+            order.state = state as i32;
+            Ok(())
+        })
+    }
+
+    async fn add_order_entry(
+        &self,
+        entry: NewOrderEntry,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        self.transaction(|orders, counters| {
+            let mut order = read_order(orders, entry.order_id)?;
+            check_version(&order, expected_version).map_err(abort)?;
+            if order.state != npb::OrderState::Open as i32 {
+                return Err(abort(RepositoryError::OrderNotOpen));
+            }
+            check_currency(&order.currency, entry.currency).map_err(abort)?;
+
+            let price_deprecated = price_deprecated(&order.currency, entry.price_in_millicents);
+            order.entries.push(npb::OrderEntry {
+                id: next_id(counters, LAST_ORDER_ENTRY_ID)?,
+                food: entry.food.clone(),
+                buyer: entry.buyer.clone(),
+                price_deprecated,
+                price_in_millicents: entry.price_in_millicents,
+                paid: false,
+                integrity_warnings: vec![],
+            });
+            order.version += 1;
+            write_order(orders, &order)?;
+            Ok(order)
+        })
+    }
+
+    async fn remove_order_entry(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        self.update_order(order_id, expected_version, |order| {
+            order.entries.retain(|entry| entry.id != order_entry_id);
+            Ok(())
+        })
+    }
+
+    async fn set_order_entry_paid(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        paid: bool,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        self.update_order(order_id, expected_version, |order| {
+            let entry = order
+                .entries
+                .iter_mut()
+                .find(|entry| entry.id == order_entry_id)
+                .ok_or(RepositoryError::OrderEntryNotFound)?;
+            entry.paid = paid;
+            Ok(())
+        })
+        .map_err(|err| match err {
+            RepositoryError::OrderNotFound => RepositoryError::OrderEntryNotFound,
+            err => err,
+        })
+    }
+
+    async fn import_order(&self, order: npb::Order) -> Result<()> {
+        self.transaction(|orders, counters| {
+            if orders.get(order_key(order.id))?.is_some() {
+                return Err(abort(backend_error(format!(
+                    "order {} already exists",
+                    order.id
+                ))));
+            }
+            write_order(orders, &order)?;
+
+            let last_order_id = read_counter(counters, LAST_ORDER_ID)?.max(order.id);
+            counters.insert(LAST_ORDER_ID, &last_order_id.to_be_bytes())?;
+            let last_order_entry_id = order
+                .entries
+                .iter()
+                .map(|entry| entry.id)
+                .fold(read_counter(counters, LAST_ORDER_ENTRY_ID)?, i32::max);
+            counters.insert(LAST_ORDER_ENTRY_ID, &last_order_entry_id.to_be_bytes())?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use futures::lock::Mutex;
+use napoli_lib::napoli as npb;
+
+use super::{
+    check_currency, check_version, NewOrder, NewOrderEntry, OrderRepository, RepositoryError,
+    Result,
+};
+use crate::calendar;
+use crate::integrity;
+use crate::model_adapters::price_deprecated;
+
+/// Keeps all orders in memory, they are gone when the server stops
+#[derive(Default)]
+pub struct MemoryOrderRepository {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    orders: BTreeMap<i32, npb::Order>,
+    last_order_id: i32,
+    last_order_entry_id: i32,
+}
+
+impl MemoryOrderRepository {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl MemoryState {
+    fn order_mut(&mut self, order_id: i32) -> Result<&mut npb::Order> {
+        self.orders
+            .get_mut(&order_id)
+            .ok_or(RepositoryError::OrderNotFound)
+    }
+}
+
+#[tonic::async_trait]
+impl OrderRepository for MemoryOrderRepository {
+    async fn get_orders(&self) -> Result<Vec<npb::Order>> {
+        let state = self.state.lock().await;
+        Ok(state.orders.values().rev().cloned().collect())
+    }
+
+    async fn get_order(&self, order_id: i32) -> Result<npb::Order> {
+        let mut state = self.state.lock().await;
+        state.order_mut(order_id).cloned()
+    }
+
+    async fn create_order(&self, new_order: NewOrder) -> Result<npb::Order> {
+        let mut state = self.state.lock().await;
+        state.last_order_id += 1;
+        let order = npb::Order {
+            id: state.last_order_id,
+            menu_url: new_order.menu_url,
+            state: npb::OrderState::Open as i32,
+            entries: vec![],
+            timestamp: calendar::format(new_order.created_at),
+            version: 1,
+            currency: new_order.currency.code().to_owned(),
+            integrity_warnings: vec![],
+        };
+        state.orders.insert(order.id, order.clone());
+        Ok(order)
+    }
+
+    async fn update_order_state(
+        &self,
+        order_id: i32,
+        order_state: npb::OrderState,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let mut state = self.state.lock().await;
+        let order = state.order_mut(order_id)?;
+        check_version(order, expected_version)?;
+        if order.state != order_state as i32 {
+            order.state = order_state as i32;
+            order.version += 1;
+            integrity::mark(order);
+        }
+        Ok(order.clone())
+    }
+
+    async fn add_order_entry(
+        &self,
+        entry: NewOrderEntry,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let mut state = self.state.lock().await;
+        let order_entry_id = state.last_order_entry_id + 1;
+        let order = state.order_mut(entry.order_id)?;
+        check_version(order, expected_version)?;
+        if order.state != npb::OrderState::Open as i32 {
+            return Err(RepositoryError::OrderNotOpen);
+        }
+        check_currency(&order.currency, entry.currency)?;
+
+        let price_deprecated = price_deprecated(&order.currency, entry.price_in_millicents);
+        order.entries.push(npb::OrderEntry {
+            id: order_entry_id,
+            food: entry.food,
+            buyer: entry.buyer,
+            price_deprecated,
+            price_in_millicents: entry.price_in_millicents,
+            paid: false,
+            integrity_warnings: vec![],
+        });
+        order.version += 1;
+        let order = order.clone();
+
+        state.last_order_entry_id = order_entry_id;
+        Ok(order)
+    }
+
+    async fn remove_order_entry(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let mut state = self.state.lock().await;
+        let order = state.order_mut(order_id)?;
+        check_version(order, expected_version)?;
+        let entries = order.entries.len();
+        order.entries.retain(|entry| entry.id != order_entry_id);
+        if order.entries.len() != entries {
+            order.version += 1;
+            integrity::mark(order);
+        }
+        Ok(order.clone())
+    }
+
+    async fn set_order_entry_paid(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        paid: bool,
+        expected_version: Option<i32>,
+    ) -> Result<npb::Order> {
+        let mut state = self.state.lock().await;
+        let order = state
+            .order_mut(order_id)
+            .map_err(|_| RepositoryError::OrderEntryNotFound)?;
+        check_version(order, expected_version)?;
+        let entry = order
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == order_entry_id)
+            .ok_or(RepositoryError::OrderEntryNotFound)?;
+        if entry.paid != paid {
+            entry.paid = paid;
+            order.version += 1;
+        }
+        Ok(order.clone())
+    }
+
+    async fn import_order(&self, mut order: npb::Order) -> Result<()> {
+        let mut state = self.state.lock().await;
+        if state.orders.contains_key(&order.id) {
+            return Err(RepositoryError::Backend(format!(
+                "order {} already exists",
+                order.id
+            )));
+        }
+
+        state.last_order_id = state.last_order_id.max(order.id);
+        for entry in &order.entries {
+            state.last_order_entry_id = state.last_order_entry_id.max(entry.id);
+        }
+        integrity::mark(&mut order);
+        state.orders.insert(order.id, order);
+        Ok(())
+    }
+}
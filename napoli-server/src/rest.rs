@@ -0,0 +1,398 @@
+//! JSON/REST gateway onto the `OrderService`.
+//!
+//! Requests and replies use the protobuf JSON mapping of the messages in `napoli-lib`, every
+//! route just forwards to the gRPC handlers of [`NapoliServer`]. The routes are described in
+//! `openapi.json`, which is served at `/api/openapi.json`.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::HttpBody;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::Json;
+use futures::future::BoxFuture;
+use napoli_lib::error_details::ErrorDetails;
+use napoli_lib::napoli as npb;
+use napoli_lib::napoli::order_service_server::OrderService;
+use serde::Deserialize;
+use tonic::transport::NamedService;
+use tower::{Service, ServiceExt};
+
+use crate::server::NapoliServer;
+
+const OPENAPI_DESCRIPTION: &str = include_str!("../openapi.json");
+
+/// Serves everything below `/api/` from the same tonic server as the gRPC services
+#[derive(Clone)]
+pub struct RestGateway {
+    router: axum::Router,
+}
+
+impl RestGateway {
+    pub fn new(server: Arc<NapoliServer>) -> Self {
+        let router = axum::Router::new()
+            .route("/api/openapi.json", get(openapi))
+            .route("/api/metrics", get(metrics))
+            .route("/api/orders", get(get_orders).post(create_order))
+            .route("/api/orders/:order_id", get(get_order))
+            .route("/api/orders/:order_id/state", put(update_order_state))
+            .route("/api/orders/:order_id/entries", post(add_order_entry))
+            .route(
+                "/api/orders/:order_id/entries/:order_entry_id",
+                delete(remove_order_entry),
+            )
+            .route(
+                "/api/orders/:order_id/entries/:order_entry_id/paid",
+                put(set_order_entry_paid),
+            )
+            .with_state(server);
+
+        RestGateway { router }
+    }
+}
+
+impl NamedService for RestGateway {
+    const NAME: &'static str = "api";
+}
+
+impl Service<http::Request<hyper::Body>> for RestGateway {
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<hyper::Body>) -> Self::Future {
+        call_router(&self.router, req)
+    }
+}
+
+/// Lets an axum router be added to the tonic server like a gRPC service
+pub fn call_router(
+    router: &axum::Router,
+    req: http::Request<hyper::Body>,
+) -> BoxFuture<'static, Result<http::Response<tonic::body::BoxBody>, Infallible>> {
+    let router = router.clone();
+    Box::pin(async move {
+        let res = router.oneshot(req).await?;
+        Ok(res.map(|body| {
+            body.map_err(|err| tonic::Status::from_error(err.into()))
+                .boxed_unsync()
+        }))
+    })
+}
+
+/// A gRPC status rendered as HTTP error with a `google.rpc.Status`-like JSON body
+pub struct RestError(tonic::Status);
+
+impl From<tonic::Status> for RestError {
+    fn from(status: tonic::Status) -> Self {
+        RestError(status)
+    }
+}
+
+impl IntoResponse for RestError {
+    fn into_response(self) -> Response {
+        let details = ErrorDetails::from_status(&self.0);
+        let mut body = serde_json::json!({
+            "code": self.0.code() as i32,
+            "message": self.0.message(),
+        });
+        if let Some(reason) = details.reason {
+            body["reason"] = reason.as_str().into();
+        }
+        if !details.field_violations.is_empty() {
+            body["fieldViolations"] = details
+                .field_violations
+                .iter()
+                .map(|violation| {
+                    serde_json::json!({
+                        "field": violation.field,
+                        "description": violation.description,
+                    })
+                })
+                .collect();
+        }
+        (http_status(self.0.code()), Json(body)).into_response()
+    }
+}
+
+type RestResult<T> = Result<Json<T>, RestError>;
+
+// Path parameters are extracted by name, as tonic already captured the `*rest` of `/api/*rest`
+#[derive(Deserialize)]
+pub struct OrderPath {
+    pub order_id: i32,
+}
+
+#[derive(Deserialize)]
+struct OrderEntryPath {
+    order_id: i32,
+    order_entry_id: i32,
+}
+
+/// `DELETE` has no body to carry the expected version
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExpectedVersion {
+    expected_version: Option<i32>,
+}
+
+/// Maps gRPC status codes to HTTP status codes the same way grpc-gateway does
+pub fn http_status(code: tonic::Code) -> StatusCode {
+    match code {
+        tonic::Code::Ok => StatusCode::OK,
+        tonic::Code::Cancelled => StatusCode::REQUEST_TIMEOUT,
+        tonic::Code::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        tonic::Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        tonic::Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        tonic::Code::NotFound => StatusCode::NOT_FOUND,
+        tonic::Code::AlreadyExists => StatusCode::CONFLICT,
+        tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+        tonic::Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        tonic::Code::FailedPrecondition => StatusCode::BAD_REQUEST,
+        tonic::Code::Aborted => StatusCode::CONFLICT,
+        tonic::Code::OutOfRange => StatusCode::BAD_REQUEST,
+        tonic::Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        tonic::Code::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        tonic::Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        tonic::Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+        tonic::Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+    }
+}
+
+async fn openapi() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        OPENAPI_DESCRIPTION,
+    )
+}
+
+/// Prometheus text format
+async fn metrics(State(server): State<Arc<NapoliServer>>) -> impl IntoResponse {
+    let metrics = server.stream_metrics();
+    let body = format!(
+        "# HELP napoli_active_streams Open streams of order updates\n\
+         # TYPE napoli_active_streams gauge\n\
+         napoli_active_streams {}\n\
+         # HELP napoli_watched_orders Orders with at least one open stream\n\
+         # TYPE napoli_watched_orders gauge\n\
+         napoli_watched_orders {}\n",
+        metrics.active_streams, metrics.watched_orders
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+async fn get_orders(State(server): State<Arc<NapoliServer>>) -> RestResult<npb::GetOrdersReply> {
+    let reply = server
+        .get_orders(tonic::Request::new(npb::GetOrdersRequest {}))
+        .await?;
+    Ok(Json(reply.into_inner()))
+}
+
+async fn create_order(
+    State(server): State<Arc<NapoliServer>>,
+    Json(request): Json<npb::CreateOrderRequest>,
+) -> RestResult<npb::SingleOrderReply> {
+    let reply = server.create_order(tonic::Request::new(request)).await?;
+    Ok(Json(reply.into_inner()))
+}
+
+async fn get_order(
+    State(server): State<Arc<NapoliServer>>,
+    Path(OrderPath { order_id }): Path<OrderPath>,
+) -> RestResult<npb::SingleOrderReply> {
+    let reply = server
+        .get_order(tonic::Request::new(npb::GetOrderRequest { order_id }))
+        .await?;
+    Ok(Json(reply.into_inner()))
+}
+
+async fn update_order_state(
+    State(server): State<Arc<NapoliServer>>,
+    Path(OrderPath { order_id }): Path<OrderPath>,
+    Json(request): Json<npb::UpdateOrderStateRequest>,
+) -> RestResult<npb::SingleOrderReply> {
+    let request = npb::UpdateOrderStateRequest {
+        order_id,
+        ..request
+    };
+    let reply = server
+        .update_order_state(tonic::Request::new(request))
+        .await?;
+    Ok(Json(reply.into_inner()))
+}
+
+async fn add_order_entry(
+    State(server): State<Arc<NapoliServer>>,
+    Path(OrderPath { order_id }): Path<OrderPath>,
+    Json(request): Json<npb::AddOrderEntryRequest>,
+) -> RestResult<npb::SingleOrderReply> {
+    let request = npb::AddOrderEntryRequest {
+        order_id,
+        ..request
+    };
+    let reply = server.add_order_entry(tonic::Request::new(request)).await?;
+    Ok(Json(reply.into_inner()))
+}
+
+async fn remove_order_entry(
+    State(server): State<Arc<NapoliServer>>,
+    Path(OrderEntryPath {
+        order_id,
+        order_entry_id,
+    }): Path<OrderEntryPath>,
+    Query(ExpectedVersion { expected_version }): Query<ExpectedVersion>,
+) -> RestResult<npb::SingleOrderReply> {
+    let request = npb::OrderEntryRequest {
+        order_id,
+        order_entry_id,
+        expected_version,
+    };
+    let reply = server
+        .remove_order_entry(tonic::Request::new(request))
+        .await?;
+    Ok(Json(reply.into_inner()))
+}
+
+async fn set_order_entry_paid(
+    State(server): State<Arc<NapoliServer>>,
+    Path(OrderEntryPath {
+        order_id,
+        order_entry_id,
+    }): Path<OrderEntryPath>,
+    Json(request): Json<npb::SetOrderEntryPaidRequest>,
+) -> RestResult<npb::SingleOrderReply> {
+    let request = npb::SetOrderEntryPaidRequest {
+        order_id,
+        order_entry_id,
+        ..request
+    };
+    let reply = server
+        .set_order_entry_paid(tonic::Request::new(request))
+        .await?;
+    Ok(Json(reply.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_bus::LocalChangeBus;
+    use crate::repository::MemoryOrderRepository;
+    use crate::streams::StreamLimits;
+    use napoli_lib::validate::Limits;
+
+    fn gateway() -> RestGateway {
+        RestGateway::new(Arc::new(NapoliServer::new(
+            Arc::new(MemoryOrderRepository::new()),
+            Arc::new(LocalChangeBus::new()),
+            StreamLimits::default(),
+            Limits::default(),
+        )))
+    }
+
+    async fn request(
+        gateway: &RestGateway,
+        method: http::Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json");
+        let body = body.map_or_else(hyper::Body::empty, |body| body.to_string().into());
+        let response = gateway
+            .router
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn routes_to_the_order_service() {
+        let gateway = gateway();
+        let (status, created) = request(
+            &gateway,
+            http::Method::POST,
+            "/api/orders",
+            Some(serde_json::json!({ "menuUrl": "https://napoli.example" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let order_id = created["order"]["id"].as_i64().unwrap();
+        assert_eq!(created["order"]["menuUrl"], "https://napoli.example");
+
+        let (status, added) = request(
+            &gateway,
+            http::Method::POST,
+            &format!("/api/orders/{}/entries", order_id),
+            Some(serde_json::json!({
+                "food": "Bufala",
+                "buyer": "Rob",
+                "priceInMillicents": "1050000",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(added["order"]["entries"][0]["food"], "Bufala");
+        assert_eq!(added["order"]["entries"][0]["priceInMillicents"], "1050000");
+
+        let (status, order) = request(
+            &gateway,
+            http::Method::GET,
+            &format!("/api/orders/{}", order_id),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(order, added);
+
+        let (status, orders) = request(&gateway, http::Method::GET, "/api/orders", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(orders["orders"].as_array().unwrap().len(), 1);
+        assert_eq!(orders["orders"][0]["id"].as_i64(), Some(order_id));
+    }
+
+    #[tokio::test]
+    async fn maps_errors_to_http() {
+        let gateway = gateway();
+        let (status, body) = request(&gateway, http::Method::GET, "/api/orders/42", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], tonic::Code::NotFound as i32);
+        assert_eq!(body["reason"], "ORDER_NOT_FOUND");
+
+        let (_, created) = request(
+            &gateway,
+            http::Method::POST,
+            "/api/orders",
+            Some(serde_json::json!({ "menuUrl": "https://napoli.example" })),
+        )
+        .await;
+        let order_id = created["order"]["id"].as_i64().unwrap();
+        let (status, body) = request(
+            &gateway,
+            http::Method::POST,
+            &format!("/api/orders/{}/entries", order_id),
+            Some(serde_json::json!({
+                "food": "x".repeat(300),
+                "buyer": "Rob",
+                "priceInMillicents": "1050000",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["reason"], "FIELD_TOO_LONG");
+        assert_eq!(body["fieldViolations"][0]["field"], "food");
+    }
+}
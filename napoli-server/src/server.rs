@@ -1,28 +1,28 @@
-use futures::lock::Mutex;
 use futures::Stream;
 use napoli_lib::napoli as npb;
-use std::collections;
+use napoli_lib::validate::Limits;
 use std::pin::Pin;
 use std::sync::Arc;
 
-use napoli_server_persistent_entities::order;
-use napoli_server_persistent_entities::order_entry;
-use sea_orm::{ActiveModelTrait, ModelTrait, QueryTrait, Set};
-use sea_orm::{DatabaseConnection, EntityTrait};
-use sea_orm::{IntoActiveModel, QueryOrder as _};
+use time::Date;
+use time_tz::Tz;
+use tokio::sync::broadcast;
 use tonic::{Request, Response, Status};
 
-use crate::errors::map_to_status;
+use crate::calendar;
+use crate::change_bus::ChangeBus;
+use crate::deltas;
 use crate::model_adapters::{self, get_order_entry_from_add_request};
-use crate::validate;
+use crate::repository::{NewOrder, NewOrderEntry, OrderRepository};
+use crate::streams::{OrderStreams, OrderUpdates, StreamLimits, StreamMetrics};
 
 pub struct NapoliServer {
-    db_handle: DatabaseConnection,
-    pub active_order_update_senders: Arc<Mutex<collections::BTreeMap<i32, OrderSender>>>,
+    repository: Arc<dyn OrderRepository>,
+    change_bus: Arc<dyn ChangeBus>,
+    streams: Arc<OrderStreams>,
+    limits: Limits,
 }
 
-type OrderSender = tokio::sync::watch::Sender<tonic::Result<npb::SingleOrderReply>>;
-
 #[tonic::async_trait]
 impl npb::order_service_server::OrderService for NapoliServer {
     type StreamOrderUpdatesStream =
@@ -35,25 +35,29 @@ impl npb::order_service_server::OrderService for NapoliServer {
         println!("stream_order_updates: Got a request: {:?}", req);
         let order_id = req.into_inner().order_id;
 
-        let initial_order = self
-            .get_order(tonic::Request::new(npb::GetOrderRequest { order_id }))
-            .await?
-            .into_inner();
+        let updates = self.subscribe_order_updates(order_id).await?;
 
-        let mut senders = self.active_order_update_senders.lock().await;
-        let rx = match senders.entry(order_id) {
-            collections::btree_map::Entry::Occupied(entry) => entry.get().subscribe(),
-            collections::btree_map::Entry::Vacant(entry) => {
-                let (tx, rx) = tokio::sync::watch::channel(Ok(initial_order));
-                entry.insert(tx);
-                rx
-            }
-        };
+        Ok(Response::new(
+            Box::pin(updates) as Self::StreamOrderUpdatesStream
+        ))
+    }
+
+    type StreamOrderDeltasStream =
+        Pin<Box<dyn Stream<Item = tonic::Result<npb::OrderDelta>> + Send>>;
+
+    async fn stream_order_deltas(
+        &self,
+        request: tonic::Request<npb::StreamOrderDeltasRequest>,
+    ) -> tonic::Result<tonic::Response<Self::StreamOrderDeltasStream>> {
+        let request = request.into_inner();
 
-        let output_stream = tokio_stream::wrappers::WatchStream::new(rx);
+        let updates = self.subscribe_order_updates(request.order_id).await?;
+        let base = self
+            .streams
+            .order_at(request.order_id, request.from_version);
 
         Ok(Response::new(
-            Box::pin(output_stream) as Self::StreamOrderUpdatesStream
+            Box::pin(deltas::deltas(updates, base)) as Self::StreamOrderDeltasStream
         ))
     }
 
@@ -63,31 +67,7 @@ impl npb::order_service_server::OrderService for NapoliServer {
     ) -> Result<Response<npb::GetOrdersReply>, Status> {
         println!("Got a request: {:?}", request);
 
-        let orders_query = order::Entity::find()
-            .order_by(order::Column::Id, sea_orm::Order::Desc)
-            .find_with_related(order_entry::Entity);
-
-        println!(
-            "Query: {:?}",
-            orders_query
-                .build(sea_orm::DatabaseBackend::Sqlite)
-                .to_string()
-        );
-
-        let orders = orders_query
-            .all(&self.db_handle)
-            .await
-            .map_err(map_to_status)?;
-
-        let orders = orders
-            .into_iter()
-            .map(|(order, entries)| {
-                println!("Order: {:?}", order);
-                // println!("Entries: {:?}", entries);
-                model_adapters::database_order_to_tonic_order(order, entries.into_iter())
-            })
-            .collect();
-
+        let orders = self.list_orders().await?;
         Ok(Response::new(npb::GetOrdersReply { orders }))
     }
 
@@ -96,50 +76,20 @@ impl npb::order_service_server::OrderService for NapoliServer {
         request: Request<npb::GetOrderRequest>,
     ) -> Result<Response<npb::SingleOrderReply>, Status> {
         let order_id = request.into_inner().order_id;
-        let orders = order::Entity::find_by_id(order_id)
-            .find_with_related(order_entry::Entity)
-            .all(&self.db_handle)
-            .await
-            .map_err(map_to_status)?;
+        let order = self.find_order(order_id).await?;
 
-        let (order, entries) = match orders.into_iter().next() {
-            Some((order, entries)) => (order, entries),
-            None => return Err(Status::not_found("order not found")),
-        };
-
-        Ok(Response::new(npb::SingleOrderReply {
-            order: Some(model_adapters::database_order_to_tonic_order(
-                order,
-                entries.into_iter(),
-            )),
-        }))
+        Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
     }
 
     async fn create_order(
         &self,
         request: tonic::Request<npb::CreateOrderRequest>,
     ) -> Result<Response<npb::SingleOrderReply>, Status> {
-        let request = request.into_inner();
-
-        validate::length("menu_url", &request.menu_url)?;
+        let order =
+            model_adapters::get_order_from_create_request(request.into_inner(), &self.limits)?;
+        let order = self.open_order(order).await?;
 
-        let order = match model_adapters::get_order_from_create_request(request) {
-            Some(order) => order,
-            None => return Err(Status::internal("no order non")),
-        };
-        println!("New Order: {:?}", order);
-        let order = order
-            .insert(&self.db_handle)
-            .await
-            .map_err(|err| Status::internal(err.to_string()))?;
-        // TODO(q3k): error handling
-        let order_entries = order
-            .find_related(order_entry::Entity)
-            .all(&self.db_handle)
-            .await
-            .map_err(|err| Status::internal(err.to_string()))?;
-        let ok_order = model_adapters::make_single_order_reply(order, order_entries);
-        Ok(Response::new(ok_order))
+        Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
     }
 
     async fn add_order_entry(
@@ -148,63 +98,12 @@ impl npb::order_service_server::OrderService for NapoliServer {
     ) -> Result<Response<npb::SingleOrderReply>, Status> {
         let request = request.into_inner();
 
-        let order = napoli_server_persistent_entities::order::Entity::find_by_id(
-            request.order_id.to_owned(),
-        )
-        .one(&self.db_handle)
-        .await
-        .map_err(|err| Status::internal(err.to_string()))?;
-        match order {
-            Some(order) => {
-                if order.state != napoli_lib::napoli::OrderState::Open as i32 {
-                    return Err(Status::invalid_argument("Order is not open"));
-                }
-            }
-            None => return Err(Status::invalid_argument("Order does not exist")),
-        }
+        let expected_version = request.expected_version;
+        let order_entry = get_order_entry_from_add_request(request, &self.limits)?;
 
-        // Add order entry
-        let order_entry = get_order_entry_from_add_request(request.to_owned());
+        let order = self.add_entry(order_entry, expected_version).await?;
 
-        validate::length("food", &request.food)?;
-        validate::length("buyer", &request.buyer)?;
-
-        let order_entry = match order_entry {
-            Some(order_entry) => order_entry,
-            None => return Err(Status::internal("Order entry parse error")),
-        };
-
-        // lmao this api
-        if *order_entry.price_in_millicents.as_ref() > 10_000_00_000 {
-            return Err(tonic::Status::invalid_argument("bro that's way too expensive bro"));
-        }
-
-        order_entry
-            .insert(&self.db_handle)
-            .await
-            .map_err(|err| Status::internal(err.to_string()))?;
-
-        // Return the order
-        let order = napoli_server_persistent_entities::order::Entity::find_by_id(request.order_id)
-            .one(&self.db_handle)
-            .await
-            .map_err(|err| Status::internal(err.to_string()))?;
-
-        match order {
-            None => Err(Status::internal("Order disappeared")),
-            Some(order) => {
-                let order_entries = order
-                    .find_related(order_entry::Entity)
-                    .all(&self.db_handle)
-                    .await
-                    .map_err(|err| Status::internal(err.to_string()))?;
-                let order = model_adapters::make_single_order_reply(order, order_entries);
-
-                self.notify_order_changed(order.order.as_ref().unwrap())
-                    .await;
-                Ok(Response::new(order))
-            }
-        }
+        Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
     }
 
     async fn update_order_state(
@@ -212,29 +111,14 @@ impl npb::order_service_server::OrderService for NapoliServer {
         request: Request<npb::UpdateOrderStateRequest>,
     ) -> Result<Response<npb::SingleOrderReply>, Status> {
         let request = request.into_inner();
-        let order_id = request.order_id.to_owned();
-        let orders = order::Entity::find_by_id(order_id)
-            .one(&self.db_handle)
-            .await
-            .map_err(map_to_status)?;
 
-        let order = match orders {
-            Some(order) => order,
-            None => return Err(Status::not_found("order not found")),
-        };
-
-        let mut order = order.into_active_model();
-        order.state = Set(request.state.to_owned());
-
-        let order: order::Model = order.update(&self.db_handle).await.map_err(map_to_status)?;
-        let entries = order
-            .find_related(order_entry::Entity)
-            .all(&self.db_handle)
-            .await
-            .map_err(map_to_status)?;
-
-        let order = model_adapters::database_order_to_tonic_order(order, entries.into_iter());
-        self.notify_order_changed(&order).await;
+        let order = self
+            .change_state(
+                request.order_id,
+                model_adapters::order_state_from_request("state", request.state)?,
+                request.expected_version,
+            )
+            .await?;
 
         Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
     }
@@ -245,24 +129,13 @@ impl npb::order_service_server::OrderService for NapoliServer {
     ) -> Result<Response<npb::SingleOrderReply>, Status> {
         let request = request.into_inner();
 
-        let _order_entry = order_entry::Entity::delete_by_id(request.order_entry_id)
-            .exec(&self.db_handle)
-            .await
-            .map_err(map_to_status)?;
-
-        let orders = order::Entity::find_by_id(request.order_id)
-            .find_with_related(order_entry::Entity)
-            .all(&self.db_handle)
-            .await
-            .map_err(map_to_status)?;
-
-        let (order, entries) = match orders.into_iter().next() {
-            Some(order) => order,
-            None => return Err(Status::not_found("order not found")),
-        };
-
-        let order = model_adapters::database_order_to_tonic_order(order, entries.into_iter());
-        self.notify_order_changed(&order).await;
+        let order = self
+            .remove_entry(
+                request.order_id,
+                request.order_entry_id,
+                request.expected_version,
+            )
+            .await?;
 
         Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
     }
@@ -273,58 +146,479 @@ impl npb::order_service_server::OrderService for NapoliServer {
     ) -> Result<Response<npb::SingleOrderReply>, Status> {
         let request = request.into_inner();
 
-        let order_entry = order_entry::Entity::find_by_id(request.order_entry_id)
-            .one(&self.db_handle)
+        let order = self
+            .change_paid(
+                request.order_id,
+                request.order_entry_id,
+                request.paid,
+                request.expected_version,
+            )
+            .await?;
+
+        Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
+    }
+}
+
+impl NapoliServer {
+    /// Streams also receive the changes other instances announce on `change_bus`. New orders and
+    /// entries are checked against `limits`
+    pub fn new(
+        repository: Arc<dyn OrderRepository>,
+        change_bus: Arc<dyn ChangeBus>,
+        stream_limits: StreamLimits,
+        limits: Limits,
+    ) -> Self {
+        let streams = OrderStreams::new(stream_limits);
+        tokio::spawn(forward_changes(
+            change_bus.subscribe(),
+            repository.clone(),
+            streams.clone(),
+        ));
+
+        NapoliServer {
+            repository,
+            change_bus,
+            streams,
+            limits,
+        }
+    }
+
+    /// What new orders and entries are checked against
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Yields the order now and after every change, until the order is done
+    pub async fn subscribe_order_updates(&self, order_id: i32) -> tonic::Result<OrderUpdates> {
+        let initial_order = self.repository.get_order(order_id).await?;
+        self.streams.subscribe(initial_order)
+    }
+
+    pub fn stream_metrics(&self) -> StreamMetrics {
+        self.streams.metrics()
+    }
+
+    // The operations all versions of the protocol are adapters over. Changes are sent to the
+    // streams of the order and announced to the other instances
+
+    pub async fn list_orders(&self) -> tonic::Result<Vec<npb::Order>> {
+        Ok(self.repository.get_orders().await?)
+    }
+
+    /// Orders created on `day` in `time_zone`, newest first
+    pub async fn list_orders_created_on(
+        &self,
+        day: Date,
+        time_zone: &Tz,
+    ) -> tonic::Result<Vec<npb::Order>> {
+        let (start, end) = calendar::bounds_of_day(day, time_zone);
+        Ok(self
+            .repository
+            .get_orders_created_between(start, end)
+            .await?)
+    }
+
+    pub async fn find_order(&self, order_id: i32) -> tonic::Result<npb::Order> {
+        Ok(self.repository.get_order(order_id).await?)
+    }
+
+    pub async fn open_order(&self, order: NewOrder) -> tonic::Result<npb::Order> {
+        Ok(self.repository.create_order(order).await?)
+    }
+
+    pub async fn add_entry(
+        &self,
+        entry: NewOrderEntry,
+        expected_version: Option<i32>,
+    ) -> tonic::Result<npb::Order> {
+        let order = self
+            .repository
+            .add_order_entry(entry, expected_version)
+            .await?;
+        self.notify_order_changed(&order).await;
+        Ok(order)
+    }
+
+    pub async fn change_state(
+        &self,
+        order_id: i32,
+        state: npb::OrderState,
+        expected_version: Option<i32>,
+    ) -> tonic::Result<npb::Order> {
+        let order = self
+            .repository
+            .update_order_state(order_id, state, expected_version)
+            .await?;
+        self.notify_order_changed(&order).await;
+        Ok(order)
+    }
+
+    pub async fn remove_entry(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        expected_version: Option<i32>,
+    ) -> tonic::Result<npb::Order> {
+        let order = self
+            .repository
+            .remove_order_entry(order_id, order_entry_id, expected_version)
+            .await?;
+        self.notify_order_changed(&order).await;
+        Ok(order)
+    }
+
+    pub async fn change_paid(
+        &self,
+        order_id: i32,
+        order_entry_id: i32,
+        paid: bool,
+        expected_version: Option<i32>,
+    ) -> tonic::Result<npb::Order> {
+        let order = self
+            .repository
+            .set_order_entry_paid(order_id, order_entry_id, paid, expected_version)
+            .await?;
+        self.notify_order_changed(&order).await;
+        Ok(order)
+    }
+
+    async fn notify_order_changed(&self, order: &napoli_lib::napoli::Order) {
+        self.streams.send(order);
+        self.change_bus.publish(order.id).await;
+    }
+}
+
+/// Reloads the orders announced on the change bus and sends them to their streams. Our own
+/// changes come back as well, but have already been sent
+async fn forward_changes(
+    mut changes: broadcast::Receiver<i32>,
+    repository: Arc<dyn OrderRepository>,
+    streams: Arc<OrderStreams>,
+) {
+    loop {
+        match changes.recv().await {
+            Ok(order_id) => refresh_order(order_id, repository.as_ref(), &streams).await,
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                // We don't know which orders we missed
+                for order_id in streams.watched_orders() {
+                    refresh_order(order_id, repository.as_ref(), &streams).await;
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+async fn refresh_order(order_id: i32, repository: &dyn OrderRepository, streams: &OrderStreams) {
+    if !streams.is_watched(order_id) {
+        return;
+    }
+
+    match repository.get_order(order_id).await {
+        Ok(order) => streams.send_if_modified(order),
+        Err(err) => println!("Failed to refresh order {}: {}", order_id, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_bus::LocalChangeBus;
+    use crate::repository::MemoryOrderRepository;
+    use futures::StreamExt;
+    use napoli_lib::error_details::{ErrorDetails, Reason};
+    use npb::order_service_server::OrderService;
+
+    async fn server_with_order() -> (NapoliServer, i32) {
+        let server = NapoliServer::new(
+            Arc::new(MemoryOrderRepository::new()),
+            Arc::new(LocalChangeBus::new()),
+            StreamLimits::default(),
+            Limits::default(),
+        );
+        let order = server
+            .create_order(Request::new(npb::CreateOrderRequest {
+                menu_url: "https://napoli.example".to_owned(),
+                currency: String::new(),
+            }))
             .await
-            .map_err(map_to_status)?;
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        (server, order.id)
+    }
 
-        let order_entry = match order_entry {
-            Some(order_entry) => order_entry,
-            None => return Err(Status::not_found("order entry not found")),
+    fn add_request(order_id: i32, food: &str) -> Request<npb::AddOrderEntryRequest> {
+        Request::new(npb::AddOrderEntryRequest {
+            order_id,
+            food: food.to_owned(),
+            buyer: "Felix".to_owned(),
+            price_deprecated: 0.0,
+            price_in_millicents: 1050000,
+            expected_version: None,
+            currency: String::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn orders_only_change_to_valid_states() {
+        let (server, order_id) = server_with_order().await;
+        let update = |state: i32| {
+            server.update_order_state(Request::new(npb::UpdateOrderStateRequest {
+                order_id,
+                state,
+                expected_version: None,
+            }))
         };
 
-        let mut order_entry = order_entry.into_active_model();
-        order_entry.paid = Set(request.paid);
+        for state in [npb::OrderState::Invalid as i32, 7, -1] {
+            let status = update(state).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert!(ErrorDetails::from_status(&status)
+                .violation("state")
+                .is_some());
+        }
+        let closed = update(npb::OrderState::Closed as i32).await.unwrap();
+        assert_eq!(
+            closed.into_inner().order.unwrap().state(),
+            npb::OrderState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn add_order_entry_validates_request() {
+        let (server, order_id) = server_with_order().await;
+
+        let too_long = "🍕".repeat(napoli_lib::limits::MAX_STR_LEN + 1);
+        let status = server
+            .add_order_entry(add_request(order_id, &too_long))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let details = ErrorDetails::from_status(&status);
+        assert_eq!(details.reason, Some(Reason::FieldTooLong));
+        assert!(details.violation("food").is_some());
+
+        // Limits count characters and whitespace is normalized
+        server
+            .add_order_entry(add_request(
+                order_id,
+                &"ü".repeat(napoli_lib::limits::MAX_STR_LEN),
+            ))
+            .await
+            .unwrap();
+        let order = server
+            .add_order_entry(add_request(order_id, "  Pizza \t Bufala "))
+            .await
+            .unwrap();
+        let entries = order.into_inner().order.unwrap().entries;
+        assert_eq!(entries[1].food, "Pizza Bufala");
+
+        let status = server
+            .add_order_entry(Request::new(npb::AddOrderEntryRequest {
+                buyer: " ".to_owned(),
+                ..add_request(order_id, "Bufala").into_inner()
+            }))
+            .await
+            .unwrap_err();
+        let details = ErrorDetails::from_status(&status);
+        assert_eq!(details.reason, Some(Reason::FieldEmpty));
+        assert!(details.violation("buyer").is_some());
 
-        let _order_entry: order_entry::Model = order_entry
-            .update(&self.db_handle)
+        let status = server
+            .add_order_entry(add_request(order_id + 1, "Bufala"))
             .await
-            .map_err(map_to_status)?;
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(
+            ErrorDetails::from_status(&status).reason,
+            Some(Reason::OrderNotFound)
+        );
+    }
 
-        let orders = order::Entity::find_by_id(request.order_id)
-            .find_with_related(order_entry::Entity)
-            .all(&self.db_handle)
+    #[tokio::test]
+    async fn stale_expected_version_is_aborted() {
+        let (server, order_id) = server_with_order().await;
+        let order = server
+            .add_order_entry(add_request(order_id, "Bufala"))
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+
+        let set_paid = |paid| {
+            Request::new(npb::SetOrderEntryPaidRequest {
+                order_id,
+                order_entry_id: order.entries[0].id,
+                paid,
+                expected_version: Some(order.version),
+            })
+        };
+        server.set_order_entry_paid(set_paid(true)).await.unwrap();
+        let status = server
+            .set_order_entry_paid(set_paid(false))
             .await
-            .map_err(map_to_status)?;
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+    }
 
-        let (order, entries) = match orders.into_iter().next() {
-            Some(order) => order,
-            None => return Err(Status::not_found("order not found")),
+    #[tokio::test]
+    async fn prices_must_be_in_the_currency_of_the_order() {
+        let (server, order_id) = server_with_order().await;
+        let in_currency = |currency: &str| {
+            let mut request = add_request(order_id, "Bufala");
+            request.get_mut().currency = currency.to_owned();
+            request
         };
 
-        let order = model_adapters::database_order_to_tonic_order(order, entries.into_iter());
-        self.notify_order_changed(&order).await;
+        let order = server
+            .add_order_entry(in_currency("EUR"))
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        assert_eq!(order.currency, "EUR");
+
+        let status = server
+            .add_order_entry(in_currency("CHF"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(
+            ErrorDetails::from_status(&status).reason,
+            Some(Reason::CurrencyMismatch)
+        );
 
-        Ok(Response::new(npb::SingleOrderReply { order: Some(order) }))
+        let status = server
+            .add_order_entry(in_currency("USD"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(ErrorDetails::from_status(&status)
+            .violation("currency")
+            .is_some());
+
+        let status = server
+            .create_order(Request::new(npb::CreateOrderRequest {
+                menu_url: String::new(),
+                currency: "USD".to_owned(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            ErrorDetails::from_status(&status).reason,
+            Some(Reason::UnknownCurrency)
+        );
     }
-}
 
-impl NapoliServer {
-    pub fn with_connection(db_handle: DatabaseConnection) -> Self {
-        NapoliServer {
-            db_handle,
-            active_order_update_senders: Default::default(),
-        }
+    #[tokio::test]
+    async fn stream_order_updates_sends_changes() {
+        let (server, order_id) = server_with_order().await;
+
+        let mut stream = server
+            .stream_order_updates(Request::new(npb::GetOrderRequest { order_id }))
+            .await
+            .unwrap()
+            .into_inner();
+        let initial = stream.next().await.unwrap().unwrap().order.unwrap();
+        assert!(initial.entries.is_empty());
+
+        server
+            .add_order_entry(add_request(order_id, "Bufala"))
+            .await
+            .unwrap();
+        let updated = stream.next().await.unwrap().unwrap().order.unwrap();
+        assert_eq!(updated.entries.len(), 1);
+        assert_eq!(updated.entries[0].food, "Bufala");
     }
 
-    async fn notify_order_changed(&self, order: &napoli_lib::napoli::Order) {
-        let mut senders = self.active_order_update_senders.lock().await;
-        if let Some(sender) = senders.get_mut(&order.id) {
-            sender
-                .send(Ok(npb::SingleOrderReply {
-                    order: Some(order.clone()),
-                }))
-                .ok();
-        }
+    #[tokio::test]
+    async fn stream_order_deltas_resumes_from_version() {
+        let (server, order_id) = server_with_order().await;
+        let known = server
+            .add_order_entry(add_request(order_id, "Bufala"))
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        server
+            .add_order_entry(add_request(order_id, "Marinara"))
+            .await
+            .unwrap();
+
+        let mut deltas = server
+            .stream_order_deltas(Request::new(npb::StreamOrderDeltasRequest {
+                order_id,
+                from_version: known.version,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let missed = deltas.next().await.unwrap().unwrap();
+        assert_eq!(missed.version, known.version + 1);
+        assert!(missed.snapshot.is_none());
+        assert!(matches!(
+            &missed.changes[..],
+            [npb::OrderChange {
+                change: Some(npb::order_change::Change::EntryAdded(entry))
+            }] if entry.food == "Marinara"
+        ));
+
+        let mut deltas = server
+            .stream_order_deltas(Request::new(npb::StreamOrderDeltasRequest {
+                order_id,
+                from_version: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let snapshot = deltas.next().await.unwrap().unwrap().snapshot.unwrap();
+        assert_eq!(snapshot.entries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stream_order_updates_sends_changes_of_other_instances() {
+        let repository = Arc::new(MemoryOrderRepository::new());
+        let change_bus = Arc::new(LocalChangeBus::new());
+        let first = NapoliServer::new(
+            repository.clone(),
+            change_bus.clone(),
+            StreamLimits::default(),
+            Limits::default(),
+        );
+        let second = NapoliServer::new(
+            repository,
+            change_bus,
+            StreamLimits::default(),
+            Limits::default(),
+        );
+
+        let order = first
+            .create_order(Request::new(npb::CreateOrderRequest {
+                menu_url: "https://napoli.example".to_owned(),
+                currency: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        let mut stream = second
+            .stream_order_updates(Request::new(npb::GetOrderRequest { order_id: order.id }))
+            .await
+            .unwrap()
+            .into_inner();
+        stream.next().await.unwrap().unwrap();
+
+        first
+            .add_order_entry(add_request(order.id, "Bufala"))
+            .await
+            .unwrap();
+        let updated = stream.next().await.unwrap().unwrap().order.unwrap();
+        assert_eq!(updated.entries.len(), 1);
     }
 }
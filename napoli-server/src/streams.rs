@@ -0,0 +1,294 @@
+//! Bookkeeping of the open streams of order updates.
+//!
+//! Every watched order has one watch channel, shared by all of its streams. A stream unregisters
+//! itself when it is dropped, and the channel goes away with the last stream of the order.
+//! Recent states of all orders that passed through are kept to resume delta streams from.
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{Stream, StreamExt};
+use napoli_lib::error_details::{ErrorDetails, Reason};
+use napoli_lib::napoli as npb;
+use tokio_stream::wrappers::WatchStream;
+
+use crate::deltas::OrderHistory;
+
+type OrderSender = tokio::sync::watch::Sender<tonic::Result<npb::SingleOrderReply>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct StreamLimits {
+    /// Open streams of a single order
+    pub per_order: usize,
+    /// Open streams of all orders together
+    pub total: usize,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        StreamLimits {
+            per_order: 100,
+            total: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamMetrics {
+    pub active_streams: usize,
+    pub watched_orders: usize,
+}
+
+pub struct OrderStreams {
+    limits: StreamLimits,
+    senders: Mutex<BTreeMap<i32, OrderSender>>,
+    history: Mutex<OrderHistory>,
+}
+
+impl OrderStreams {
+    pub fn new(limits: StreamLimits) -> Arc<Self> {
+        Arc::new(OrderStreams {
+            limits,
+            senders: Default::default(),
+            history: Default::default(),
+        })
+    }
+
+    /// Starts with `order`, unless the order is already watched, in which case the stream starts
+    /// with the latest state all other streams got
+    pub fn subscribe(self: &Arc<Self>, order: npb::Order) -> tonic::Result<OrderUpdates> {
+        self.history.lock().unwrap().record(&order);
+        let mut senders = self.senders.lock().unwrap();
+
+        let active_streams: usize = senders.values().map(|tx| tx.receiver_count()).sum();
+        if active_streams >= self.limits.total {
+            return Err(too_many_streams("Too many open streams"));
+        }
+
+        let order_id = order.id;
+        let rx = match senders.get(&order_id) {
+            Some(tx) if tx.receiver_count() >= self.limits.per_order => {
+                return Err(too_many_streams("Too many open streams for this order"));
+            }
+            Some(tx) => tx.subscribe(),
+            None => {
+                let (tx, rx) =
+                    tokio::sync::watch::channel(Ok(npb::SingleOrderReply { order: Some(order) }));
+                senders.insert(order_id, tx);
+                rx
+            }
+        };
+
+        Ok(OrderUpdates {
+            updates: WatchStream::new(rx),
+            finished: false,
+            _subscription: Subscription {
+                streams: self.clone(),
+                order_id,
+            },
+        })
+    }
+
+    pub fn is_watched(&self, order_id: i32) -> bool {
+        self.senders.lock().unwrap().contains_key(&order_id)
+    }
+
+    pub fn watched_orders(&self) -> Vec<i32> {
+        self.senders.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Sends the order to all of its streams, see [`OrderStreams::send_if_modified`]
+    pub fn send(&self, order: &npb::Order) {
+        self.send_if_modified(order.clone());
+    }
+
+    /// Sends the order to all of its streams, unless they already have this state or a newer one.
+    /// Concurrent changes send after their commits without a lock, and reloads may fetch an order
+    /// before a change that is sent first, so older states can arrive late
+    pub fn send_if_modified(&self, order: npb::Order) {
+        self.history.lock().unwrap().record(&order);
+        if let Some(tx) = self.senders.lock().unwrap().get(&order.id) {
+            tx.send_if_modified(|reply| match reply {
+                Ok(npb::SingleOrderReply {
+                    order: Some(current),
+                }) if current.version > order.version || *current == order => false,
+                _ => {
+                    *reply = Ok(npb::SingleOrderReply { order: Some(order) });
+                    true
+                }
+            });
+        }
+    }
+
+    /// The order as it was at `version`, if that is still known
+    pub fn order_at(&self, order_id: i32, version: i32) -> Option<npb::Order> {
+        self.history.lock().unwrap().get(order_id, version).cloned()
+    }
+
+    pub fn metrics(&self) -> StreamMetrics {
+        let senders = self.senders.lock().unwrap();
+        StreamMetrics {
+            active_streams: senders.values().map(|tx| tx.receiver_count()).sum(),
+            watched_orders: senders.len(),
+        }
+    }
+}
+
+fn too_many_streams(message: &str) -> tonic::Status {
+    ErrorDetails::new(Reason::TooManyStreams).into_status(tonic::Code::ResourceExhausted, message)
+}
+
+/// Removes the channel of the order once its last stream is gone
+struct Subscription {
+    streams: Arc<OrderStreams>,
+    order_id: i32,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut senders = self.streams.senders.lock().unwrap();
+        if let Some(tx) = senders.get(&self.order_id) {
+            if tx.receiver_count() == 0 {
+                senders.remove(&self.order_id);
+            }
+        }
+    }
+}
+
+/// Yields the latest state of the order after every change and ends after the order is done
+pub struct OrderUpdates {
+    updates: WatchStream<tonic::Result<npb::SingleOrderReply>>,
+    finished: bool,
+    // Dropped after `updates`, so the receiver is already gone when it checks for the last one
+    _subscription: Subscription,
+}
+
+impl Stream for OrderUpdates {
+    type Item = tonic::Result<npb::SingleOrderReply>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        let item = futures::ready!(self.updates.poll_next_unpin(cx));
+        if let Some(Ok(npb::SingleOrderReply { order: Some(order) })) = &item {
+            self.finished = order.state == npb::OrderState::Done as i32;
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(state: npb::OrderState) -> npb::Order {
+        npb::Order {
+            id: 1,
+            state: state as i32,
+            ..Default::default()
+        }
+    }
+
+    fn version(version: i32) -> npb::Order {
+        npb::Order {
+            version,
+            ..order(npb::OrderState::Open)
+        }
+    }
+
+    #[tokio::test]
+    async fn older_states_arriving_late_are_not_sent() {
+        use futures::FutureExt;
+
+        let streams = OrderStreams::new(StreamLimits::default());
+        let mut updates = streams.subscribe(version(1)).unwrap();
+        let mut deltas = Box::pin(crate::deltas::deltas(
+            streams.subscribe(version(1)).unwrap(),
+            None,
+        ));
+        let latest_version =
+            |reply: tonic::Result<npb::SingleOrderReply>| reply.unwrap().order.unwrap().version;
+        assert_eq!(latest_version(updates.next().await.unwrap()), 1);
+        assert_eq!(deltas.next().await.unwrap().unwrap().version, 1);
+
+        streams.send(&version(2));
+        streams.send(&version(1));
+        streams.send_if_modified(version(1));
+        assert_eq!(latest_version(updates.next().await.unwrap()), 2);
+        assert_eq!(deltas.next().await.unwrap().unwrap().version, 2);
+        assert!(updates.next().now_or_never().is_none());
+        assert!(deltas.next().now_or_never().is_none());
+
+        // Another state of the same version still replaces it
+        let closed = npb::Order {
+            state: npb::OrderState::Closed as i32,
+            ..version(2)
+        };
+        streams.send(&closed);
+        assert_eq!(updates.next().await.unwrap().unwrap().order, Some(closed));
+    }
+
+    #[tokio::test]
+    async fn last_stream_removes_channel() {
+        let streams = OrderStreams::new(StreamLimits::default());
+        let first = streams.subscribe(order(npb::OrderState::Open)).unwrap();
+        let second = streams.subscribe(order(npb::OrderState::Open)).unwrap();
+        assert_eq!(
+            streams.metrics(),
+            StreamMetrics {
+                active_streams: 2,
+                watched_orders: 1
+            }
+        );
+
+        drop(first);
+        assert!(streams.is_watched(1));
+        drop(second);
+        assert!(!streams.is_watched(1));
+        assert_eq!(streams.metrics().active_streams, 0);
+    }
+
+    #[tokio::test]
+    async fn streams_are_limited() {
+        let streams = OrderStreams::new(StreamLimits {
+            per_order: 1,
+            total: 2,
+        });
+        let _first = streams.subscribe(order(npb::OrderState::Open)).unwrap();
+        let status = streams
+            .subscribe(order(npb::OrderState::Open))
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        let other_order = npb::Order {
+            id: 2,
+            ..order(npb::OrderState::Open)
+        };
+        let _second = streams.subscribe(other_order.clone()).unwrap();
+        let status = streams
+            .subscribe(npb::Order {
+                id: 3,
+                ..other_order
+            })
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_when_order_is_done() {
+        let streams = OrderStreams::new(StreamLimits::default());
+        let mut updates = streams.subscribe(order(npb::OrderState::Open)).unwrap();
+        updates.next().await.unwrap().unwrap();
+
+        streams.send(&order(npb::OrderState::Done));
+        let done = updates.next().await.unwrap().unwrap().order.unwrap();
+        assert_eq!(done.state, npb::OrderState::Done as i32);
+        assert!(updates.next().await.is_none());
+    }
+}
@@ -0,0 +1,474 @@
+//! HTTPS on the server's port, for gRPC, grpc-web and the HTTP APIs alike.
+//!
+//! The certificate and key files are checked for changes every `reload_interval_secs`, and new
+//! connections get the new certificate, so renewing it needs no restart. A renewal that can't be
+//! loaded, e.g. because only one of the files was written yet, keeps the old certificate in use.
+//!
+//! With an admin client CA, clients may present a certificate it signed, and only those reach
+//! the [`ADMIN_PATHS`]. Clients without one can use everything else as before.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use futures::future::{self, Either};
+use futures::Stream;
+use hyper::body::HttpBody;
+use hyper::Body;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, ClientHello, ResolvesServerCert,
+};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::body::BoxBody;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+
+use crate::config;
+
+/// Paths, or prefixes of them, only admins may use once there is an admin client CA
+pub const ADMIN_PATHS: &[&str] = &["/api/metrics", "/grpc.reflection.v1alpha.ServerReflection/"];
+
+/// Clients that take longer to finish their handshake are disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub struct TlsError(String);
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+type Result<T> = std::result::Result<T, TlsError>;
+
+fn read_error(path: &Path, err: impl fmt::Display) -> TlsError {
+    TlsError(format!("can't read {}: {}", path.display(), err))
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path).map_err(|err| read_error(path, err))?;
+    let certificates =
+        rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|err| read_error(path, err))?;
+    if certificates.is_empty() {
+        return Err(read_error(path, "no PEM certificates in it"));
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path).map_err(|err| read_error(path, err))?;
+    let mut reader = BufReader::new(file);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|err| read_error(path, err))? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(read_error(path, "no PEM private key in it")),
+        }
+    }
+}
+
+/// The certificate every handshake gets, replaced when its files change
+struct ReloadingCertificate {
+    certificate_file: PathBuf,
+    key_file: PathBuf,
+    current: RwLock<Loaded>,
+}
+
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    /// Of the files when they were last loaded
+    modified: Option<SystemTime>,
+}
+
+/// Of the newer of the two files
+fn modified(certificate_file: &Path, key_file: &Path) -> Option<SystemTime> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+    let certificate = modified(certificate_file).ok()?;
+    let key = modified(key_file).ok()?;
+    Some(certificate.max(key))
+}
+
+fn read_certified_key(certificate_file: &Path, key_file: &Path) -> Result<Arc<CertifiedKey>> {
+    let certificates = read_certificates(certificate_file)?;
+    let key =
+        sign::any_supported_type(&read_key(key_file)?).map_err(|err| read_error(key_file, err))?;
+    Ok(Arc::new(CertifiedKey::new(certificates, key)))
+}
+
+impl ReloadingCertificate {
+    fn load(certificate_file: &Path, key_file: &Path) -> Result<Self> {
+        let modified = modified(certificate_file, key_file);
+        let key = read_certified_key(certificate_file, key_file)?;
+        Ok(ReloadingCertificate {
+            certificate_file: certificate_file.to_owned(),
+            key_file: key_file.to_owned(),
+            current: RwLock::new(Loaded { key, modified }),
+        })
+    }
+
+    /// Loads the files again if they changed since they were last loaded. Files that fail to
+    /// load are tried again once they change again
+    fn reload_if_changed(&self) -> Result<bool> {
+        let modified = modified(&self.certificate_file, &self.key_file);
+        if modified == self.current.read().unwrap().modified {
+            return Ok(false);
+        }
+        let loaded = read_certified_key(&self.certificate_file, &self.key_file);
+        let mut current = self.current.write().unwrap();
+        current.modified = modified;
+        current.key = loaded?;
+        Ok(true)
+    }
+
+    fn watch(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => println!(
+                        "Reloaded the TLS certificate {}",
+                        self.certificate_file.display()
+                    ),
+                    Ok(false) => {}
+                    Err(err) => println!("Keeping the current TLS certificate: {}", err),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertificate {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().key.clone())
+    }
+}
+
+/// What the server needs to accept TLS connections
+pub struct Termination {
+    certificate: Arc<ReloadingCertificate>,
+    server_config: Arc<ServerConfig>,
+    reload_interval: Duration,
+}
+
+impl Termination {
+    /// Reads the files `tls` names. `None` without a certificate, which is plain HTTP
+    pub fn load(tls: &config::Tls) -> Result<Option<Self>> {
+        let (Some(certificate_file), Some(key_file)) = (&tls.certificate, &tls.key) else {
+            return Ok(None);
+        };
+        let certificate = Arc::new(ReloadingCertificate::load(certificate_file, key_file)?);
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &tls.admin_client_ca {
+            Some(admin_client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for ca in read_certificates(admin_client_ca)? {
+                    roots
+                        .add(&ca)
+                        .map_err(|err| read_error(admin_client_ca, err))?;
+                }
+                builder
+                    .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut server_config = builder.with_cert_resolver(certificate.clone());
+        // grpc-web and the HTTP APIs also work over HTTP/1.1
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Some(Termination {
+            certificate,
+            server_config: Arc::new(server_config),
+            reload_interval: Duration::from_secs(tls.reload_interval_secs),
+        }))
+    }
+
+    /// Accepts TLS connections on `listener` and starts reloading the certificate. Handshakes
+    /// run concurrently, and failed ones are only logged, as an error would stop the server
+    pub fn incoming(
+        self,
+        listener: TcpListener,
+    ) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+        self.certificate.watch(self.reload_interval);
+
+        let acceptor = TlsAcceptor::from(self.server_config);
+        let (sender, receiver) = tokio::sync::mpsc::channel(64);
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        // e.g. too many open files, which may pass
+                        println!("Failed to accept a connection: {}", err);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(Ok(stream)).await;
+                        }
+                        Ok(Err(err)) => println!("TLS handshake with {} failed: {}", peer, err),
+                        Err(_) => println!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+        ReceiverStream::new(receiver)
+    }
+}
+
+/// Whether the request came over a connection with a client certificate, which the admin client
+/// CA signed: clients can only present those
+pub fn is_admin<B>(request: &http::Request<B>) -> bool {
+    request
+        .extensions()
+        .get::<TlsConnectInfo<TcpConnectInfo>>()
+        .and_then(|info| info.peer_certs())
+        .is_some_and(|certificates| !certificates.is_empty())
+}
+
+/// Turns away everybody but admins from the [`ADMIN_PATHS`], if `enabled`
+#[derive(Clone, Copy, Debug)]
+pub struct AdminOnlyLayer {
+    enabled: bool,
+}
+
+impl AdminOnlyLayer {
+    pub fn new(enabled: bool) -> Self {
+        AdminOnlyLayer { enabled }
+    }
+}
+
+impl<S> tower::Layer<S> for AdminOnlyLayer {
+    type Service = AdminOnly<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdminOnly {
+            inner,
+            enabled: self.enabled,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdminOnly<S> {
+    inner: S,
+    enabled: bool,
+}
+
+impl<S> tower::Service<http::Request<Body>> for AdminOnly<S>
+where
+    S: tower::Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, future::Ready<std::result::Result<Self::Response, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let path = request.uri().path();
+        let admin_path = ADMIN_PATHS.iter().any(|prefix| path.starts_with(prefix));
+        if !self.enabled || !admin_path || is_admin(&request) {
+            return Either::Left(self.inner.call(request));
+        }
+
+        let message = "needs an admin client certificate";
+        let is_grpc = request
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/grpc"));
+        let response = if is_grpc {
+            tonic::Status::permission_denied(message).to_http()
+        } else {
+            http::Response::builder()
+                .status(http::StatusCode::FORBIDDEN)
+                .body(
+                    Body::from(message)
+                        .map_err(|err| tonic::Status::internal(err.to_string()))
+                        .boxed_unsync(),
+                )
+                .expect("Should be a valid response")
+        };
+        Either::Right(future::ready(Ok(response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tokio_rustls::rustls::{ClientConfig, ServerName};
+    use tokio_rustls::TlsConnector;
+    use tonic::transport::server::Connected;
+    use tower::{Layer, ServiceExt};
+
+    const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls");
+
+    fn testdata(file: &str) -> PathBuf {
+        Path::new(TESTDATA).join(file)
+    }
+
+    fn client(admin: bool) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&read_certificates(&testdata("ca.pem")).unwrap()[0])
+            .unwrap();
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = if admin {
+            builder
+                .with_single_cert(
+                    read_certificates(&testdata("admin.pem")).unwrap(),
+                    read_key(&testdata("admin-key.pem")).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// The certificate the server presented, and whether it saw an admin
+    async fn connect(
+        incoming: &mut (impl Stream<Item = io::Result<TlsStream<TcpStream>>> + Unpin),
+        port: u16,
+        admin: bool,
+    ) -> (Certificate, bool) {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let localhost = ServerName::try_from("localhost").unwrap();
+        let client = client(admin).connect(localhost, stream).await.unwrap();
+        let server = incoming.next().await.unwrap().unwrap();
+
+        let presented = client.get_ref().1.peer_certificates().unwrap()[0].clone();
+        let saw_admin = server
+            .connect_info()
+            .peer_certs()
+            .is_some_and(|certificates| !certificates.is_empty());
+        (presented, saw_admin)
+    }
+
+    #[tokio::test]
+    async fn serves_renewed_certificates_and_recognizes_admins() {
+        let dir = tempfile::tempdir().unwrap();
+        let certificate_file = dir.path().join("server.pem");
+        let key_file = dir.path().join("server-key.pem");
+        std::fs::copy(testdata("server.pem"), &certificate_file).unwrap();
+        std::fs::copy(testdata("server-key.pem"), &key_file).unwrap();
+        let tls = config::Tls {
+            certificate: Some(certificate_file.clone()),
+            key: Some(key_file.clone()),
+            admin_client_ca: Some(testdata("ca.pem")),
+            // Reloads are triggered by the test
+            reload_interval_secs: 3600,
+        };
+
+        let termination = Termination::load(&tls).unwrap().unwrap();
+        let certificate = termination.certificate.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut incoming = Box::pin(termination.incoming(listener));
+
+        let original = read_certificates(&testdata("server.pem")).unwrap()[0].clone();
+        assert_eq!(
+            connect(&mut incoming, port, false).await,
+            (original.clone(), false)
+        );
+        assert_eq!(
+            connect(&mut incoming, port, true).await,
+            (original.clone(), true)
+        );
+        assert!(!certificate.reload_if_changed().unwrap());
+
+        // A renewal that isn't complete yet keeps the old certificate
+        std::fs::write(&key_file, "").unwrap();
+        assert!(certificate.reload_if_changed().is_err());
+        assert_eq!(connect(&mut incoming, port, false).await.0, original);
+
+        std::fs::copy(testdata("server-renewed.pem"), &certificate_file).unwrap();
+        std::fs::copy(testdata("server-renewed-key.pem"), &key_file).unwrap();
+        // Copies within the same clock tick would look unchanged
+        File::options()
+            .write(true)
+            .open(&key_file)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(certificate.reload_if_changed().unwrap());
+        let renewed = read_certificates(&testdata("server-renewed.pem")).unwrap()[0].clone();
+        assert_eq!(connect(&mut incoming, port, false).await.0, renewed);
+    }
+
+    #[tokio::test]
+    async fn only_admins_reach_admin_paths() {
+        let inner = tower::service_fn(|_: http::Request<Body>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::empty_body()))
+        });
+        let status = |enabled: bool, path: &'static str, content_type: &'static str| {
+            let service = AdminOnlyLayer::new(enabled).layer(inner);
+            async move {
+                let request = http::Request::builder()
+                    .uri(path)
+                    .header(http::header::CONTENT_TYPE, content_type)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = service.oneshot(request).await.unwrap();
+                (
+                    response.status(),
+                    response
+                        .headers()
+                        .get("grpc-status")
+                        .map(|status| status.to_str().unwrap().to_owned()),
+                )
+            }
+        };
+
+        assert_eq!(
+            status(true, "/api/metrics", "text/plain").await,
+            (http::StatusCode::FORBIDDEN, None)
+        );
+        assert_eq!(
+            status(
+                true,
+                "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+                "application/grpc"
+            )
+            .await,
+            (
+                http::StatusCode::OK,
+                Some((tonic::Code::PermissionDenied as i32).to_string())
+            )
+        );
+        assert_eq!(
+            status(true, "/api/orders", "application/json").await,
+            (http::StatusCode::OK, None)
+        );
+        assert_eq!(
+            status(false, "/api/metrics", "text/plain").await,
+            (http::StatusCode::OK, None)
+        );
+    }
+}
@@ -0,0 +1,582 @@
+//! Version 2 of the protocol. Like version 1, it is an adapter over the operations of
+//! [`NapoliServer`], so clients of both versions see the same orders and changes.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::{Stream, StreamExt};
+use napoli_lib::napoli::{self as npb, v2};
+use napoli_lib::validate::ValidationError;
+use napoli_lib::Currency;
+use tonic::{Request, Response};
+
+use crate::calendar;
+use crate::model_adapters;
+use crate::repository::{NewOrderEntry, RepositoryError};
+use crate::server::NapoliServer;
+use crate::validate;
+
+pub struct NapoliServerV2 {
+    server: Arc<NapoliServer>,
+}
+
+impl NapoliServerV2 {
+    pub fn new(server: Arc<NapoliServer>) -> Self {
+        NapoliServerV2 { server }
+    }
+}
+
+/// Ids are the decimal ids of version 1. Slugs that aren't can't name anything
+fn parse_id(slug: &str, not_found: RepositoryError) -> tonic::Result<i32> {
+    slug.parse().map_err(|_| not_found.into())
+}
+
+fn order_id(slug: &str) -> tonic::Result<i32> {
+    parse_id(slug, RepositoryError::OrderNotFound)
+}
+
+fn order_entry_id(slug: &str) -> tonic::Result<i32> {
+    parse_id(slug, RepositoryError::OrderEntryNotFound)
+}
+
+/// The paths of `mask`, which have to be among `supported`, each once. A path named twice would
+/// change the order twice, and fail the second time if a version is expected
+fn mask_paths(
+    mask: Option<prost_types::FieldMask>,
+    supported: &[&str],
+) -> tonic::Result<Vec<String>> {
+    let paths = mask.map(|mask| mask.paths).unwrap_or_default();
+    if paths.is_empty() {
+        return Err(validate::invalid(
+            "update_mask",
+            "must name the fields to change",
+        ));
+    }
+    match paths
+        .iter()
+        .find(|path| !supported.contains(&path.as_str()))
+    {
+        Some(path) => Err(validate::invalid(
+            "update_mask",
+            &format!("can't change {}", path),
+        )),
+        None => {
+            let mut unique = Vec::with_capacity(paths.len());
+            for path in paths {
+                if !unique.contains(&path) {
+                    unique.push(path);
+                }
+            }
+            Ok(unique)
+        }
+    }
+}
+
+/// The order after the changes of a mask, which always names a field
+fn changed(order: Option<npb::Order>) -> tonic::Result<npb::Order> {
+    order.ok_or_else(|| validate::invalid("update_mask", "must name the fields to change"))
+}
+
+fn state_to_v2(state: i32) -> v2::OrderState {
+    match npb::OrderState::from_i32(state) {
+        Some(npb::OrderState::Open) => v2::OrderState::Open,
+        Some(npb::OrderState::Closed) => v2::OrderState::Closed,
+        Some(npb::OrderState::Done) => v2::OrderState::Done,
+        Some(npb::OrderState::Invalid) | None => v2::OrderState::Unspecified,
+    }
+}
+
+fn state_from_v2(state: i32) -> Option<npb::OrderState> {
+    match v2::OrderState::from_i32(state)? {
+        v2::OrderState::Open => Some(npb::OrderState::Open),
+        v2::OrderState::Closed => Some(npb::OrderState::Closed),
+        v2::OrderState::Done => Some(npb::OrderState::Done),
+        v2::OrderState::Unspecified => None,
+    }
+}
+
+/// `None` if the timestamp isn't valid, which only orders imported from elsewhere can have
+fn timestamp_to_v2(timestamp: &str) -> Option<prost_types::Timestamp> {
+    let time = calendar::parse(timestamp)?;
+    Some(prost_types::Timestamp {
+        seconds: time.unix_timestamp(),
+        nanos: time.nanosecond() as i32,
+    })
+}
+
+pub fn order_to_v2(order: npb::Order) -> v2::Order {
+    let currency_code = match order.currency.as_str() {
+        "" => Currency::default().code().to_owned(),
+        _ => order.currency,
+    };
+
+    v2::Order {
+        id: order.id.to_string(),
+        menu_url: order.menu_url,
+        state: state_to_v2(order.state) as i32,
+        entries: order
+            .entries
+            .into_iter()
+            .map(|entry| v2::OrderEntry {
+                id: entry.id.to_string(),
+                food: entry.food,
+                buyer: entry.buyer,
+                price: Some(v2::Money {
+                    currency_code: currency_code.clone(),
+                    millicents: entry.price_in_millicents,
+                }),
+                paid: entry.paid,
+                integrity_warnings: entry.integrity_warnings,
+            })
+            .collect(),
+        create_time: timestamp_to_v2(&order.timestamp),
+        version: order.version,
+        currency_code,
+        integrity_warnings: order.integrity_warnings,
+    }
+}
+
+#[tonic::async_trait]
+impl v2::order_service_server::OrderService for NapoliServerV2 {
+    async fn list_orders(
+        &self,
+        request: Request<v2::ListOrdersRequest>,
+    ) -> tonic::Result<Response<v2::ListOrdersResponse>> {
+        let request = request.into_inner();
+        let time_zone = calendar::time_zone(&request.time_zone)
+            .ok_or_else(|| validate::invalid("time_zone", "is not an IANA time zone"))?;
+        let day = match request.created {
+            None => None,
+            Some(v2::list_orders_request::Created::CreatedOn(day)) => {
+                Some(calendar::parse_day(&day).ok_or_else(|| {
+                    validate::invalid("created_on", "is not a day like 2026-10-19")
+                })?)
+            }
+            Some(v2::list_orders_request::Created::CreatedToday(false)) => {
+                return Err(validate::invalid(
+                    "created_today",
+                    "must be true if it is set",
+                ))
+            }
+            Some(v2::list_orders_request::Created::CreatedToday(true)) => {
+                Some(calendar::day_of(calendar::now(), time_zone))
+            }
+        };
+        let orders = match day {
+            Some(day) => self.server.list_orders_created_on(day, time_zone).await?,
+            None => self.server.list_orders().await?,
+        };
+        Ok(Response::new(v2::ListOrdersResponse {
+            orders: orders.into_iter().map(order_to_v2).collect(),
+        }))
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<v2::GetOrderRequest>,
+    ) -> tonic::Result<Response<v2::Order>> {
+        let order_id = order_id(&request.into_inner().order_id)?;
+        let order = self.server.find_order(order_id).await?;
+        Ok(Response::new(order_to_v2(order)))
+    }
+
+    async fn create_order(
+        &self,
+        request: Request<v2::CreateOrderRequest>,
+    ) -> tonic::Result<Response<v2::Order>> {
+        let request = request.into_inner();
+        let order = model_adapters::new_order(
+            self.server.limits(),
+            &request.menu_url,
+            "currency_code",
+            &request.currency_code,
+        )?;
+        let order = self.server.open_order(order).await?;
+        Ok(Response::new(order_to_v2(order)))
+    }
+
+    async fn update_order(
+        &self,
+        request: Request<v2::UpdateOrderRequest>,
+    ) -> tonic::Result<Response<v2::Order>> {
+        let request = request.into_inner();
+        let paths = mask_paths(request.update_mask, &["state"])?;
+        let changes = request
+            .order
+            .ok_or_else(|| validate::invalid("order", "must be set"))?;
+        let order_id = order_id(&changes.id)?;
+
+        let mut order = None;
+        for path in paths {
+            if path == "state" {
+                let state = state_from_v2(changes.state)
+                    .ok_or_else(|| validate::invalid("order.state", "is not a valid state"))?;
+                order = Some(
+                    self.server
+                        .change_state(order_id, state, request.expected_version)
+                        .await?,
+                );
+            }
+        }
+        Ok(Response::new(order_to_v2(changed(order)?)))
+    }
+
+    async fn add_order_entry(
+        &self,
+        request: Request<v2::AddOrderEntryRequest>,
+    ) -> tonic::Result<Response<v2::Order>> {
+        let request = request.into_inner();
+        let order_id = order_id(&request.order_id)?;
+        let price = validate::field("price", request.price.ok_or(ValidationError::Empty))?;
+        let (food, buyer, currency) = model_adapters::entry_fields(
+            self.server.limits(),
+            &request.food,
+            &request.buyer,
+            "price.currency_code",
+            &price.currency_code,
+        )?;
+        let price = validate::field(
+            "price.millicents",
+            self.server.limits().price_from_millicents(price.millicents),
+        )?;
+
+        let entry = NewOrderEntry {
+            order_id,
+            buyer: buyer.into_inner(),
+            food: food.into_inner(),
+            price_in_millicents: price.millicents(),
+            currency,
+        };
+        let order = self
+            .server
+            .add_entry(entry, request.expected_version)
+            .await?;
+        Ok(Response::new(order_to_v2(order)))
+    }
+
+    async fn update_order_entry(
+        &self,
+        request: Request<v2::UpdateOrderEntryRequest>,
+    ) -> tonic::Result<Response<v2::Order>> {
+        let request = request.into_inner();
+        let paths = mask_paths(request.update_mask, &["paid"])?;
+        let order_id = order_id(&request.order_id)?;
+        let changes = request
+            .entry
+            .ok_or_else(|| validate::invalid("entry", "must be set"))?;
+        let order_entry_id = order_entry_id(&changes.id)?;
+
+        let mut order = None;
+        for path in paths {
+            if path == "paid" {
+                order = Some(
+                    self.server
+                        .change_paid(
+                            order_id,
+                            order_entry_id,
+                            changes.paid,
+                            request.expected_version,
+                        )
+                        .await?,
+                );
+            }
+        }
+        Ok(Response::new(order_to_v2(changed(order)?)))
+    }
+
+    async fn remove_order_entry(
+        &self,
+        request: Request<v2::RemoveOrderEntryRequest>,
+    ) -> tonic::Result<Response<v2::Order>> {
+        let request = request.into_inner();
+        let order_id = order_id(&request.order_id)?;
+        let order_entry_id = order_entry_id(&request.entry_id)?;
+
+        let order = self
+            .server
+            .remove_entry(order_id, order_entry_id, request.expected_version)
+            .await?;
+        Ok(Response::new(order_to_v2(order)))
+    }
+
+    type WatchOrderStream = Pin<Box<dyn Stream<Item = tonic::Result<v2::Order>> + Send>>;
+
+    async fn watch_order(
+        &self,
+        request: Request<v2::WatchOrderRequest>,
+    ) -> tonic::Result<Response<Self::WatchOrderStream>> {
+        let order_id = order_id(&request.into_inner().order_id)?;
+        let updates = self.server.subscribe_order_updates(order_id).await?;
+
+        let orders = updates.map(|update| {
+            update.map(|reply| order_to_v2(reply.order.expect("updates always have an order")))
+        });
+        Ok(Response::new(Box::pin(orders) as Self::WatchOrderStream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_bus::LocalChangeBus;
+    use crate::repository::MemoryOrderRepository;
+    use crate::streams::StreamLimits;
+    use napoli_lib::error_details::{ErrorDetails, Reason};
+    use napoli_lib::validate::Limits;
+    use npb::order_service_server::OrderService as _;
+    use v2::order_service_server::OrderService;
+
+    fn servers() -> (Arc<NapoliServer>, NapoliServerV2) {
+        let server = Arc::new(NapoliServer::new(
+            Arc::new(MemoryOrderRepository::new()),
+            Arc::new(LocalChangeBus::new()),
+            StreamLimits::default(),
+            Limits::default(),
+        ));
+        (server.clone(), NapoliServerV2::new(server))
+    }
+
+    fn mask(paths: &[&str]) -> Option<prost_types::FieldMask> {
+        Some(prost_types::FieldMask {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        })
+    }
+
+    async fn create_order(v2_server: &NapoliServerV2, currency_code: &str) -> v2::Order {
+        v2_server
+            .create_order(Request::new(v2::CreateOrderRequest {
+                menu_url: "https://napoli.example".to_owned(),
+                currency_code: currency_code.to_owned(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    fn add_request(order_id: &str, millicents: i64) -> Request<v2::AddOrderEntryRequest> {
+        Request::new(v2::AddOrderEntryRequest {
+            order_id: order_id.to_owned(),
+            food: "Bufala".to_owned(),
+            buyer: "Rob".to_owned(),
+            price: Some(v2::Money {
+                currency_code: "CHF".to_owned(),
+                millicents,
+            }),
+            expected_version: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn both_versions_share_orders() {
+        let (v1_server, v2_server) = servers();
+        let order = create_order(&v2_server, "CHF").await;
+        assert_eq!(order.state(), v2::OrderState::Open);
+        assert_eq!(order.currency_code, "CHF");
+        assert!(order.create_time.is_some());
+
+        let added = v2_server
+            .add_order_entry(add_request(&order.id, 1_050_000))
+            .await
+            .unwrap()
+            .into_inner();
+        let price = added.entries[0].price.clone().unwrap();
+        assert_eq!(
+            (price.currency_code.as_str(), price.millicents),
+            ("CHF", 1_050_000)
+        );
+
+        let paid = v2_server
+            .update_order_entry(Request::new(v2::UpdateOrderEntryRequest {
+                order_id: order.id.clone(),
+                entry: Some(v2::OrderEntry {
+                    id: added.entries[0].id.clone(),
+                    paid: true,
+                    ..Default::default()
+                }),
+                update_mask: mask(&["paid"]),
+                expected_version: Some(added.version),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(paid.entries[0].paid);
+
+        let v1_order = v1_server
+            .get_order(Request::new(npb::GetOrderRequest {
+                order_id: order.id.parse().unwrap(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        assert_eq!(order_to_v2(v1_order), paid);
+
+        let closed = v2_server
+            .update_order(Request::new(v2::UpdateOrderRequest {
+                order: Some(v2::Order {
+                    id: order.id.clone(),
+                    state: v2::OrderState::Closed as i32,
+                    ..Default::default()
+                }),
+                update_mask: mask(&["state"]),
+                expected_version: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(closed.state(), v2::OrderState::Closed);
+        assert_eq!(closed.version, paid.version + 1);
+
+        // A field named twice is changed once
+        let done = v2_server
+            .update_order(Request::new(v2::UpdateOrderRequest {
+                order: Some(v2::Order {
+                    id: order.id.clone(),
+                    state: v2::OrderState::Done as i32,
+                    ..Default::default()
+                }),
+                update_mask: mask(&["state", "state"]),
+                expected_version: Some(closed.version),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(done.state(), v2::OrderState::Done);
+        assert_eq!(done.version, closed.version + 1);
+    }
+
+    #[tokio::test]
+    async fn updates_need_supported_masks() {
+        let (_, v2_server) = servers();
+        let order = create_order(&v2_server, "").await;
+        assert_eq!(order.currency_code, "EUR");
+
+        let update = |paths: &[&str]| {
+            Request::new(v2::UpdateOrderRequest {
+                order: Some(order.clone()),
+                update_mask: mask(paths),
+                expected_version: None,
+            })
+        };
+        for paths in [&[][..], &["menu_url"], &["state", "entries"]] {
+            let status = v2_server.update_order(update(paths)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            let details = ErrorDetails::from_status(&status);
+            assert_eq!(details.reason, Some(Reason::InvalidField));
+            assert!(details.violation("update_mask").is_some());
+        }
+
+        // No order can be in the unspecified state
+        let mut unspecified = update(&["state"]);
+        unspecified.get_mut().order.as_mut().unwrap().state = v2::OrderState::Unspecified as i32;
+        let status = v2_server.update_order(unspecified).await.unwrap_err();
+        assert!(ErrorDetails::from_status(&status)
+            .violation("order.state")
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn entries_are_checked_like_in_version_1() {
+        let (_, v2_server) = servers();
+        let order = create_order(&v2_server, "EUR").await;
+
+        let status = v2_server
+            .add_order_entry(add_request(&order.id, 1_050_000))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let status = v2_server
+            .add_order_entry(add_request(&order.id, -1))
+            .await
+            .unwrap_err();
+        assert!(ErrorDetails::from_status(&status)
+            .violation("price.millicents")
+            .is_some());
+
+        let mut without_price = add_request(&order.id, 0);
+        without_price.get_mut().price = None;
+        let status = v2_server.add_order_entry(without_price).await.unwrap_err();
+        assert_eq!(
+            ErrorDetails::from_status(&status).reason,
+            Some(Reason::FieldEmpty)
+        );
+
+        let status = v2_server
+            .get_order(Request::new(v2::GetOrderRequest {
+                order_id: "not-an-order".to_owned(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn watch_order_sends_changes() {
+        let (_, v2_server) = servers();
+        let order = create_order(&v2_server, "CHF").await;
+
+        let mut orders = v2_server
+            .watch_order(Request::new(v2::WatchOrderRequest {
+                order_id: order.id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(orders.next().await.unwrap().unwrap(), order);
+
+        let added = v2_server
+            .add_order_entry(add_request(&order.id, 1_050_000))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(orders.next().await.unwrap().unwrap(), added);
+    }
+
+    #[tokio::test]
+    async fn orders_of_a_day() {
+        use v2::list_orders_request::Created;
+
+        let (_, v2_server) = servers();
+        let order = create_order(&v2_server, "").await;
+        let list = |created: Option<Created>, time_zone: &str| {
+            v2_server.list_orders(Request::new(v2::ListOrdersRequest {
+                created,
+                time_zone: time_zone.to_owned(),
+            }))
+        };
+        let ids = |response: Response<v2::ListOrdersResponse>| -> Vec<String> {
+            let orders = response.into_inner().orders;
+            orders.into_iter().map(|order| order.id).collect()
+        };
+
+        // Today is another day on either side of the date line, but the order is new in both
+        for time_zone in ["", "Pacific/Kiritimati", "Pacific/Pago_Pago"] {
+            let today = list(Some(Created::CreatedToday(true)), time_zone);
+            assert_eq!(ids(today.await.unwrap()), std::slice::from_ref(&order.id));
+        }
+        let yesterday = calendar::now() - time::Duration::DAY;
+        let created_on = Created::CreatedOn(
+            calendar::day_of(yesterday, time_tz::timezones::db::UTC).to_string(),
+        );
+        assert!(ids(list(Some(created_on), "").await.unwrap()).is_empty());
+        assert_eq!(ids(list(None, "Europe/Berlin").await.unwrap()).len(), 1);
+
+        for (created, time_zone, field) in [
+            (None, "Europe/Napoli", "time_zone"),
+            (
+                Some(Created::CreatedOn("19.10.2026".to_owned())),
+                "",
+                "created_on",
+            ),
+            (Some(Created::CreatedToday(false)), "", "created_today"),
+        ] {
+            let status = list(created, time_zone).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert!(ErrorDetails::from_status(&status)
+                .violation(field)
+                .is_some());
+        }
+    }
+}
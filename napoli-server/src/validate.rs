@@ -1,10 +1,28 @@
-pub fn length(name: &'static str, string: &str) -> Result<(), tonic::Status> {
-    if string.len() > napoli_lib::limits::MAX_STR_LEN {
-        return Err(tonic::Status::invalid_argument(format!(
-            "{} exceeds the maximum limit {}",
-            name,
-            napoli_lib::limits::MAX_STR_LEN,
-        )));
-    }
-    Ok(())
+use napoli_lib::error_details::{ErrorDetails, Reason};
+use napoli_lib::validate::ValidationError;
+
+/// Turns a failed check of the request field `name` into an invalid argument status naming it
+pub fn field<T>(
+    name: &'static str,
+    checked: Result<T, ValidationError>,
+) -> Result<T, tonic::Status> {
+    checked.map_err(|err| {
+        let description = err.to_string();
+        ErrorDetails::new(err.reason())
+            .with_violation(name, description.clone())
+            .into_status(
+                tonic::Code::InvalidArgument,
+                format!("{} {}", name, description),
+            )
+    })
+}
+
+/// An invalid argument status for the request field `name` that no [`ValidationError`] covers
+pub fn invalid(name: &str, description: &str) -> tonic::Status {
+    ErrorDetails::new(Reason::InvalidField)
+        .with_violation(name, description)
+        .into_status(
+            tonic::Code::InvalidArgument,
+            format!("{} {}", name, description),
+        )
 }